@@ -0,0 +1,29 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Registry of the 24 bit MAC prefixes (MA-L) as published by the IEEE at https://standards-oui.ieee.org/oui/oui.csv.
+// Replace the file with a fresh download to update the embedded vendors
+const IEEE_OUI_CSV: &str = "data/oui.csv";
+
+// Compact the registry into the table embedded by network_oui: one "prefix vendor" line per assignment like nmap-mac-prefixes
+fn main() {
+    println!("cargo:rerun-if-changed={}", IEEE_OUI_CSV);
+
+    let mut reader = csv::Reader::from_path(IEEE_OUI_CSV).unwrap_or_else(|e| panic!("failed to open {}: {}", IEEE_OUI_CSV, e));
+    let mut table = String::new();
+    for record in reader.records() {
+        let record = record.unwrap_or_else(|e| panic!("failed to parse {}: {}", IEEE_OUI_CSV, e));
+        // Registry, Assignment, Organization Name, Organization Address
+        let (Some(assignment), Some(vendor)) = (record.get(1), record.get(2)) else {
+            continue;
+        };
+        let vendor = vendor.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if assignment.len() == 6 && !vendor.is_empty() {
+            table.push_str(&format!("{} {}\n", assignment, vendor));
+        }
+    }
+
+    let out_dir = env::var("OUT_DIR").expect("cargo sets OUT_DIR for build scripts");
+    fs::write(Path::new(&out_dir).join("oui_table.txt"), table).expect("failed to write the OUI table");
+}
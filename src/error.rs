@@ -0,0 +1,65 @@
+use std::io;
+use std::net::IpAddr;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ScanError {
+    #[error("Invalid target '{target}': {reason}")]
+    InvalidTarget { target: String, reason: String },
+
+    #[error("Invalid IP range {start} to {end}: make sure the start is logically smaller than the end")]
+    InvertedRange { start: IpAddr, end: IpAddr },
+
+    #[error("Invalid port specification '{spec}': {reason}")]
+    InvalidPorts { spec: String, reason: String },
+
+    #[error("Invalid DNS server '{server}': expected an IP address, optionally with a port like 10.0.0.53:5353 or [fd00::53]:53")]
+    InvalidDnsServer { server: String },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl ScanError {
+    // Distinct process exit code per kind of error, so scripts can tell them apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ScanError::InvalidTarget { .. } => 2,
+            ScanError::InvertedRange { .. } => 3,
+            ScanError::InvalidPorts { .. } => 4,
+            ScanError::Io(_) => 6,
+            ScanError::InvalidDnsServer { .. } => 7,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct_and_not_success() {
+        let errors = [
+            ScanError::InvalidTarget { target: String::new(), reason: String::new() },
+            ScanError::InvertedRange { start: IpAddr::from([10, 0, 0, 2]), end: IpAddr::from([10, 0, 0, 1]) },
+            ScanError::InvalidPorts { spec: String::new(), reason: String::new() },
+            ScanError::Io(io::Error::other("test")),
+            ScanError::InvalidDnsServer { server: String::new() },
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(ScanError::exit_code).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        // 1 is what a panic exits with
+        assert!(codes.iter().all(|code| *code > 1));
+    }
+
+    #[test]
+    fn messages_name_the_offending_input() {
+        let error = ScanError::InvalidTarget { target: String::from("10.0.0.300"), reason: String::from("not an IP address, CIDR, range or hostname") };
+        assert_eq!(error.to_string(), "Invalid target '10.0.0.300': not an IP address, CIDR, range or hostname");
+        let error = ScanError::InvertedRange { start: IpAddr::from([10, 0, 0, 2]), end: IpAddr::from([10, 0, 0, 1]) };
+        assert!(error.to_string().contains("10.0.0.2 to 10.0.0.1"));
+    }
+}
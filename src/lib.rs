@@ -0,0 +1,9 @@
+pub mod error;
+pub mod network;
+pub mod report;
+pub mod scanner;
+
+pub use crate::error::ScanError;
+pub use crate::network::network_core::{DiscoveryMethod, DnsCache, DnsClient, DnsError, PingOptions, PingStats, PortInfo, PortScanResult, PortState, ProbeError, Reason, Status};
+pub use crate::network::network_services::ServiceInfo;
+pub use crate::scanner::{Probes, Scanner, ScannerBuilder};
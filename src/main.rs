@@ -1,113 +1,169 @@
-#![allow(dead_code)]
-
-mod network;
-use crate::network::network_core::{analyse_interfaces, ping_host_surge, reverse_dns_lookup, scan_ports_tcp, PortScanResult};
-use crate::network::network_helpers::{split_ip_range, create_ip_from_range};
+use network_scanner::network::network_core::{DnsClient, TCP_PORTS};
+use network_scanner::network::network_oui::OuiTable;
+use network_scanner::network::network_interfaces::{analyse_interfaces, attached_networks, MIN_LOCAL_IPV4_PREFIX};
+use network_scanner::network::network_helpers::{parse_dns_server, parse_port_spec, format_port_spec};
+use network_scanner::network::network_targets::{read_target_file, resolve_targets};
+use network_scanner::report::{open_output, OutputFormat, ScanMetadata, ScanReport};
+use network_scanner::report::report_csv::write_csv_report;
+use network_scanner::report::report_json::{write_json_report, write_ndjson_result};
+use network_scanner::report::report_text::{print_interfaces, print_results};
+use network_scanner::report::report_xml::write_xml_report;
+use network_scanner::{PortScanResult, Probes, ScanError, Scanner};
 
 use std::time::Duration;
-use std::net::{Ipv4Addr};
-use ipnet::Ipv4Net;
-use std::sync::{Arc, Mutex};
-use surge_ping::{Client, Config};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use chrono::Utc;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use tokio::task;
-use futures::future::join_all;
+use futures::StreamExt;
 
 use clap::{Parser, ArgAction};
 
-const TCP_PORTS: [u16; 11] = [20,21,22,23,25,53,80,110,143,443,445];
-
 #[derive(Parser)]
 struct Cli {
-    #[arg(help = "Either IPv4 address without subnet mask or CIDR notation. If it is CIDR notation, ip_to is ignored. If IPv4 addres and ip_to not set, ip_from is scanned, otherwise range from ip_from to ip_to")]
-    ip_from: Option<String>,
+    #[arg(help = "Targets to scan, separated by spaces or commas: IPv4/IPv6 addresses, CIDR notation (IPv6 /120 and narrower), ranges like 10.0.0.5-10.0.0.40, octet ranges like 10.0.1-3.1-254 or hostnames")]
+    targets: Vec<String>,
     
-    #[arg(help = "If ip_from is a IPv4 address, this is the end of the range. Must be greater than ip_from")]
-    ip_to: Option<String>,
+    #[arg(help = "Read targets from a file, one per line. '-' reads from stdin")]
+    #[arg(short, long)]
+    input_file: Option<PathBuf>,
     
-    #[arg(help = "Timeout in milliseconds")]
-    #[arg(short, long, default_value_t=100)]
-    timeout: u32,
+    #[arg(help = "Scan every IPv4 network attached to a non-loopback interface, up to a /16")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    local: bool,
     
-    #[arg(help = "If IP range is specified, number of IP addresses each worker receives")]
-    #[arg(short, long, default_value_t=10)]
-    chunksize: usize,
+    #[arg(help = "Scan the IPv4 networks attached to this interface, e.g. eth0")]
+    #[arg(long)]
+    interface: Option<String>,
     
-    #[arg(short, long, action = ArgAction::SetTrue)]
-    verboose: bool,
-}
-
-fn parse_ip_input(ip_from: &str, ip_to: Option<String>) -> (bool, Ipv4Addr, Option<Ipv4Addr>) {
-    // Try ip_from as Ipv4Net
-    if let Ok(ipv4_net) = ip_from.parse::<Ipv4Net>() {
-        let ips: Vec<Ipv4Addr> = ipv4_net.hosts().collect();
-        let ip_from: Ipv4Addr = ips[0];
-        let ip_to: Ipv4Addr = ips[ips.len()-1];
-        println!("Parsed ip_from as CIDR: {} to {}", ip_from, ip_to);
-        return (true, ip_from, Some(ip_to));
-    }
-
-    // If not Ipv4Net, try Ipv4Addr
-    match ip_from.parse::<Ipv4Addr>() {
-        Ok(ipv4_addr) => {
-            let ip_from: Ipv4Addr = ipv4_addr;
-            println!("Parsed ip_from as Ipv4Addr: {}", ip_from);
-
-            // Now check if ip_to is set and valid
-            if let Some(ip_to) = ip_to {
-                match ip_to.parse::<Ipv4Addr>() {
-                    Ok(ipv4_addr) => {
-                        println!("Parsed ip_to as Ipv4Addr: {}", ipv4_addr);
-                        let ip_to: Ipv4Addr = ipv4_addr;
-                        if ip_from >= ip_to {
-                            panic!("Invalid IP Range: {:?} to {:?}. Make sure ip_from is logically smaller than ip_to", ip_from, ip_to);
-                        }
-                        return (true, ip_from, Some(ip_to));
-                    },
-                    Err(_) => {
-                        panic!("Failed to parse ip_to '{}' as Ipv4Addr", ip_to);
-                    }
-                }
-            } else {
-                return (false, ip_from, None);
-            }
-            
-        }
-        Err(_) => {
-            // Handle invalid input ip_form
-            panic!("Failed to parse ip_from '{}' as either Ipv4Addr or Ipv4Net",ip_from);
-        }
-    }
-}
-
-fn print_results(results: &Vec<network::network_core::PortScanResult>, n_total: u32, n_up: u32) {
-    
-    println!("--------------------------------------------------------------------------------------------------------------------------------\n");
-    println!("RESULTS:");
-    println!("Total IPs scanned: {}", n_total);
-    println!("IPs UP: {}", n_up);
-    
-    println!("--------------------------------------------------------------------------------------------------------------------------------\n");
-    println!("IPs UP:");
-    for result in results.iter() {
-        if result.status == network::network_core::Status::Up {
-            println!(
-                "IP: {:?} ; Status: {:?} ; Hostname: {:?} ; Open TCP Ports: {:?}",
-                result.ip_address,
-                result.status,
-                result.hostname,
-                result.open_tcp_ports,
-            );
-        }
-    };
-
-    println!("\nIPs DOWN:");
-    for result in results.iter() {
-        if result.status == network::network_core::Status::Down {
-            println!("IP: {:?} ; Status: {:?}", result.ip_address, result.status);
-        }
-    };
+    #[arg(help = "Targets to skip, same syntax as the targets")]
+    #[arg(long)]
+    exclude: Vec<String>,
+    
+    #[arg(help = "Read targets to skip from a file, one per line. '-' reads from stdin")]
+    #[arg(long)]
+    exclude_file: Option<PathBuf>,
+    
+    #[arg(help = "Timeout in milliseconds for every probe: TCP connect, ping and DNS lookup with the OS resolver. The specific timeouts take precedence")]
+    #[arg(short, long)]
+    timeout: Option<u32>,
+    
+    #[arg(help = "Timeout of a single TCP connect in milliseconds [default: 100]")]
+    #[arg(long)]
+    connect_timeout: Option<u32>,
+    
+    #[arg(help = "Timeout of a reverse DNS lookup in milliseconds [default: 2000]. With --dns-server the timeout of every single query [default: 1000]")]
+    #[arg(long)]
+    dns_timeout: Option<u32>,
+    
+    #[arg(help = "Ask this nameserver instead of the OS resolver, for the hostnames of the hosts and to resolve hostname targets. An address with an optional port, e.g. 10.0.0.53 or 127.0.0.1:5353. Repeat it or separate with commas for more servers, they are asked in order")]
+    #[arg(long, value_delimiter = ',')]
+    dns_server: Vec<String>,
+    
+    #[arg(help = "How many more times a nameserver of --dns-server that doesn't answer is asked before the next one")]
+    #[arg(long, default_value_t=2, requires = "dns_server")]
+    dns_retries: usize,
+    
+    #[arg(help = "Also look up the TXT records of every hostname found, needs --dns-server")]
+    #[arg(long, action = ArgAction::SetTrue, requires = "dns_server")]
+    dns_txt: bool,
+    
+    #[arg(help = "Never look up the hostnames of the hosts, like nmap -n")]
+    #[arg(short = 'n', long, action = ArgAction::SetTrue, conflicts_with = "resolve_all")]
+    no_dns: bool,
+    
+    #[arg(help = "Also look up the hostnames of the hosts that are down, like nmap -R. By default only hosts that are up are looked up")]
+    #[arg(short = 'R', long, action = ArgAction::SetTrue)]
+    resolve_all: bool,
+    
+    #[arg(help = "Identify the services on the open ports by their banner and by protocol probes (HTTP, TLS, SMTP, Redis, ...), like nmap -sV")]
+    #[arg(long, visible_alias = "sV", action = ArgAction::SetTrue)]
+    service_detection: bool,
+    
+    #[arg(help = "Complete a TLS handshake with the open TLS ports (443, 465, 993, 8443, ...) and the ports where --service-detection found TLS, and record protocol, cipher and certificate")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    tls: bool,
+    
+    #[arg(help = "Fetch / from the open HTTP(S) ports (80, 443, 8080, 8443, ...) and the ports where --service-detection found HTTP or TLS, and record status, Server header, title, redirect and favicon hash")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    http: bool,
+    
+    #[arg(help = "Time to wait for a banner, the reply to a service probe, a TLS handshake message or an HTTP response in milliseconds [default: 2000]")]
+    #[arg(long)]
+    service_timeout: Option<u32>,
+    
+    #[arg(help = "Derive the ping and TCP connect timeouts from the measured round trip times, the configured timeouts are only used until the first reply")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    adaptive_timeout: bool,
+    
+    #[arg(help = "Also probe these TCP ports to find hosts that block ICMP, an accepted or refused connection counts as alive. Same syntax as --ports, defaults to 80,443")]
+    #[arg(long, num_args = 0..=1, default_missing_value = "80,443")]
+    tcp_discovery: Option<String>,
+    
+    #[arg(help = "Don't send ARP requests to targets on directly connected networks")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_arp: bool,
+    
+    #[arg(help = "Don't ping the hosts. Without --tcp-discovery every host is assumed to be up")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    no_icmp: bool,
+    
+    #[arg(help = "Number of ICMP echo requests sent to every host")]
+    #[arg(long, default_value_t=1)]
+    ping_count: u16,
+    
+    #[arg(help = "Time between two echo requests to the same host in milliseconds")]
+    #[arg(long, default_value_t=100)]
+    ping_interval: u32,
+    
+    #[arg(help = "Time to wait for the reply to a single echo request in milliseconds [default: 2000]")]
+    #[arg(long)]
+    ping_timeout: Option<u32>,
+    
+    #[arg(help = "Maximum number of hosts probed at the same time")]
+    #[arg(long, default_value_t=64)]
+    hosts_in_flight: usize,
+    
+    #[arg(help = "Maximum number of TCP port probes in flight, summed over all hosts")]
+    #[arg(long, default_value_t=256)]
+    ports_in_flight: usize,
+    
+    #[arg(help = "TCP ports to scan, nmap style: e.g. '22,80,8000-8100', '-' for all 65535 ports or 'top-100'. Defaults to 20-23,25,53,80,110,143,443,445")]
+    #[arg(short, long)]
+    ports: Option<String>,
+    
+    #[arg(help = "Scan the TCP ports with SYN packets instead of connects. Needs root or CAP_NET_RAW, falls back to the connect scan without")]
+    #[arg(long, visible_alias = "sS", action = ArgAction::SetTrue)]
+    syn: bool,
+    
+    #[arg(help = "Also scan UDP ports, with a payload for DNS, DHCP, TFTP, NTP, NetBIOS, SNMP, SSDP and mDNS. Ports are open (reply), closed (ICMP port unreachable) or open|filtered (no answer)")]
+    #[arg(long, visible_alias = "sU", action = ArgAction::SetTrue)]
+    udp: bool,
+    
+    #[arg(help = "UDP ports to scan with --udp, same syntax as --ports. Defaults to 53,67,69,123,137,161,514,1900,5353")]
+    #[arg(long)]
+    udp_ports: Option<String>,
+    
+    #[arg(help = "Time to wait for a reply or an ICMP error after every UDP datagram in milliseconds [default: 1000]")]
+    #[arg(long)]
+    udp_timeout: Option<u32>,
+    
+    #[arg(help = "Look up MAC vendors in this file in addition to the embedded table. Reads the IEEE oui.txt, nmap-mac-prefixes and Wireshark manuf formats")]
+    #[arg(long)]
+    oui_file: Option<PathBuf>,
+    
+    #[arg(help = "Format of the final report. ndjson streams one host per line as soon as it is scanned")]
+    #[arg(long, value_enum, default_value_t=OutputFormat::Text)]
+    output_format: OutputFormat,
+    
+    #[arg(help = "With --output-format csv, write one row per host and probed port with its state instead of one row per host")]
+    #[arg(long, action = ArgAction::SetTrue)]
+    csv_per_port: bool,
+    
+    #[arg(help = "Write the report to this file instead of stdout")]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -115,262 +171,223 @@ async fn main() {
 
     let args = Cli::parse();
 
-    let timeout = args.timeout;
-    let chunksize = args.chunksize;
+    // Report errors as a short message and an exit code per kind of error instead of a panic
+    if let Err(e) = run(args).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
 
-    // Always analyse network interfaces
-    println!("--------------------------------------------------------------------------------------------------------------------------------\n");
-    println!("Analyse interfaces ...");
-    analyse_interfaces();
-    println!("--------------------------------------------------------------------------------------------------------------------------------\n");
+async fn run(args: Cli) -> Result<(), ScanError> {
 
-    // If neither IP from nor IP to are set, we're done
-    if args.ip_from.is_none() && args.ip_to.is_none() {
-        println!("No IP from or to specified, we're done");
-        return
+    // Stdin can only be read once, the second list would silently be empty
+    if args.input_file.as_deref() == Some(Path::new("-")) && args.exclude_file.as_deref() == Some(Path::new("-")) {
+        return Err(ScanError::InvalidTarget {
+            target: String::from("-"),
+            reason: String::from("--input-file and --exclude-file can't both read from stdin"),
+        });
     }
 
-    // Unwrap the ip_from into str and parse the inputs
-    let ip_from_string: String = args.ip_from.expect("IP from must be supplied");
-    let (do_range, ip_from, ip_to) = parse_ip_input(&ip_from_string, args.ip_to);
+    // `A B` used to be the range from A to B, now it's two targets. Point out the range notation to old habits
+    if let [start, end] = args.targets.as_slice() {
+        if let (Ok(start), Ok(end)) = (start.parse::<IpAddr>(), end.parse::<IpAddr>()) {
+            if start.is_ipv4() == end.is_ipv4() && start < end {
+                eprintln!("Warning: scanning {} and {} as two single hosts, for the range between them use {}-{}", start, end, start, end);
+            }
+        }
+    }
 
-    // Create a ping client
-    let config = Config::default();
-    let client: Arc<Client> = Arc::new(Client::new(&config).unwrap());    
-    
-    if !do_range {
-        println!("Scanning single IP {:?}", ip_from);
-        
-        // Ping and resolve hostname and tcp port scan
-        let status = ping_host_surge(&client, ip_from, false).await;
-        let hostname = reverse_dns_lookup(ip_from).await;
-        let open_tcp_ports = scan_ports_tcp(ip_from, Duration::from_millis(timeout as u64), &TCP_PORTS).await;
+    // Parse the selected ports, fall back to the default list
+    let ports: Vec<u16> = match args.ports {
+        Some(spec) => parse_port_spec(&spec)?,
+        None => TCP_PORTS.to_vec(),
+    };
 
-        let ping_result = PortScanResult{
-            ip_address: ip_from,
-            status: status,
-            hostname: hostname,
-            open_tcp_ports: open_tcp_ports,
-        };
-        
-        println!("Result ping {:?}", ping_result);
+    let udp_ports: Option<Vec<u16>> = match args.udp_ports.as_deref() {
+        Some(spec) => Some(parse_port_spec(spec)?),
+        None => None,
+    };
 
-        // // Test serializing and deserializing
-        // let serialized = serde_json::to_string(&success_ping).unwrap();
-        // println!("Serialized: {:?}", serialized);
-        // let deserialized: network::network_core::PortScanResult = serde_json::from_str(&serialized).unwrap();
-        // println!("Deserialized: {:?}", deserialized);
+    let discovery_ports: Option<Vec<u16>> = match args.tcp_discovery.as_deref() {
+        Some(spec) => Some(parse_port_spec(spec)?),
+        None => None,
+    };
 
+    // The built-in DNS client replaces the OS resolver if nameservers are given
+    let dns_client: Option<DnsClient> = if args.dns_server.is_empty() {
+        None
     } else {
+        let servers = args.dns_server.iter().map(|spec| parse_dns_server(spec)).collect::<Result<Vec<_>, ScanError>>()?;
+        let mut client = DnsClient::new(servers).retries(args.dns_retries);
+        // Only --dns-timeout, the general timeout is meant for whole lookups and would be multiplied by the retries and servers
+        if let Some(timeout) = args.dns_timeout {
+            client = client.timeout(Duration::from_millis(timeout as u64));
+        }
+        Some(client)
+    };
 
-        let ip_to = ip_to.expect("If we want to scan a range, ip_to must be supplied");
-        
-        println!("Scanning IP Range {:?} to {:?}", ip_from.to_string(), ip_to.to_string());
-
-        // Split IP Range
-        let (ip_ranges, n_ips) = split_ip_range(ip_from, ip_to, chunksize);
-
-        let progress_bar = Arc::new(Mutex::new(ProgressBar::new(n_ips as u64)));
-        progress_bar.lock().unwrap().set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({eta})")
-                .expect("Invalid template format"),
-        );
-
-    
-        // Run concurrently
-        let shared_vector = Arc::new(Mutex::new(Vec::new()));
-        let mut tasks = vec![];
-        for range in ip_ranges {
-            // Generate IPs for the current range
-            let ip_to_check = create_ip_from_range(range);
-            let vector = Arc::clone(&shared_vector);
-            let pb = Arc::clone(&progress_bar);
-            let client_clone = Arc::clone(&client);
-            // Spawn a new async task for each IP range
-            let task = task::spawn(async move {
-                for ip_addr in ip_to_check {
-                    let ip: Ipv4Addr = ip_addr.parse().unwrap();
-                    
-                    // Ping and resolve hostname and tcp port scan
-                    let status = ping_host_surge(&client_clone, ip, false).await;
-                    let hostname = reverse_dns_lookup(ip).await;
-                    let open_tcp_ports = scan_ports_tcp(ip, Duration::from_millis(timeout as u64), &TCP_PORTS).await;
-
-                    // Create a new result
-                    let ping_result = PortScanResult {
-                        ip_address: ip,
-                        status: status,
-                        hostname: hostname,
-                        open_tcp_ports: open_tcp_ports,
-                    };
+    // Status messages go to stderr, stdout only carries the report so it can be piped into other tools
+    // Analyse network interfaces, unless a machine readable report is requested
+    let local = args.local || args.interface.is_some();
+    let no_targets = args.targets.is_empty() && args.input_file.is_none() && !local;
+    let interfaces = analyse_interfaces();
+    if args.output_format == OutputFormat::Text || no_targets {
+        println!("--------------------------------------------------------------------------------------------------------------------------------\n");
+        println!("Analyse interfaces ...");
+        print_interfaces(&mut std::io::stdout(), &interfaces)?;
+        println!("--------------------------------------------------------------------------------------------------------------------------------\n");
+    }
 
-                    // Lock the vector to write the result
-                    let mut locked_vector = vector.lock().unwrap();
-                    locked_vector.push(ping_result);
-                    // Lock pb and increment
-                    let pb = pb.lock().unwrap();
-                    pb.inc(1);
-                }
-            });
+    // If no targets are set, we're done
+    if no_targets {
+        println!("No targets specified, we're done");
+        return Ok(())
+    }
 
-            tasks.push(task);
+    // Gather the targets from the command line and the files, merge them and drop the excluded ones
+    let mut includes: Vec<String> = args.targets.clone();
+    if local {
+        let networks = attached_networks(&interfaces, args.interface.as_deref())?;
+        if networks.is_empty() {
+            eprintln!("No IPv4 network of a /{} or smaller attached, nothing local to scan", MIN_LOCAL_IPV4_PREFIX);
         }
-        // Wait for all async tasks to finish
-        join_all(tasks).await;
-        // Print the results
-        let mut locked_vector = shared_vector.lock().unwrap();
-        
-        // Pretty print the results
-        // Sort by IP
-        locked_vector.sort_by(|a, b| a.ip_address.cmp(&b.ip_address));
+        includes.extend(networks.iter().map(|network| network.to_string()));
+    }
+    if let Some(path) = args.input_file.as_deref() {
+        includes.extend(read_target_file(path)?);
+    }
+    let mut excludes: Vec<String> = args.exclude.clone();
+    if let Some(path) = args.exclude_file.as_deref() {
+        excludes.extend(read_target_file(path)?);
+    }
+    let targets = resolve_targets(&includes, &excludes, dns_client.as_ref()).await?;
+    if targets.is_empty() {
+        eprintln!("No targets left to scan after applying the exclusions");
+    }
 
-        // Gather information about how many IP scanned, how many are up etc
-        let n_total: u32 = locked_vector.len() as u32;
-        let mut n_up: u32 = 0;
-        locked_vector.iter().for_each(|result| {
-            if result.status == network::network_core::Status::Up {
-                n_up += 1;
-            }
+    // Open the report destination up front, ndjson writes to it while scanning
+    let mut output = open_output(args.output.as_deref())?;
+    let output_format = args.output_format;
+    let start_time = Utc::now();
+
+    let mut builder = Scanner::builder()
+        .target_set(&targets)
+        .ports(&ports)
+        .concurrency(args.hosts_in_flight)
+        .port_concurrency(args.ports_in_flight)
+        .ping_count(args.ping_count)
+        .ping_interval(Duration::from_millis(args.ping_interval as u64))
+        .adaptive_timeout(args.adaptive_timeout)
+        .probes(Probes {
+            arp: !args.no_arp,
+            icmp: !args.no_icmp,
+            tcp_discovery: discovery_ports.is_some(),
+            syn: args.syn,
+            udp: args.udp,
+            reverse_dns: !args.no_dns,
+            reverse_dns_all: args.resolve_all,
+            dns_txt: args.dns_txt,
+            service_detection: args.service_detection,
+            tls: args.tls,
+            http: args.http,
+            ..Probes::default()
         });
-
-        // Print the results
-        print_results(&locked_vector, n_total, n_up);
+    if let Some(dns_client) = dns_client {
+        builder = builder.dns_client(dns_client);
+    }
+    if let Some(udp_ports) = udp_ports.as_deref() {
+        builder = builder.udp_ports(udp_ports);
+    }
+    if let Some(discovery_ports) = discovery_ports.as_deref() {
+        builder = builder.discovery_ports(discovery_ports);
+    }
+    // The general timeout first, so the specific ones can override it
+    if let Some(timeout) = args.timeout {
+        builder = builder.timeout(Duration::from_millis(timeout as u64));
+    }
+    if let Some(timeout) = args.connect_timeout {
+        builder = builder.connect_timeout(Duration::from_millis(timeout as u64));
+    }
+    if let Some(timeout) = args.ping_timeout {
+        builder = builder.ping_timeout(Duration::from_millis(timeout as u64));
+    }
+    if let Some(timeout) = args.dns_timeout {
+        builder = builder.dns_timeout(Duration::from_millis(timeout as u64));
+    }
+    if let Some(timeout) = args.udp_timeout {
+        builder = builder.udp_timeout(Duration::from_millis(timeout as u64));
+    }
+    if let Some(timeout) = args.service_timeout {
+        builder = builder.service_timeout(Duration::from_millis(timeout as u64));
+    }
+    if let Some(path) = args.oui_file.as_deref() {
+        builder = builder.oui_table(OuiTable::with_file(path)?);
+    }
+    let scanner = builder.build()?;
+    let syn_scan = scanner.syn_scan();
+    if args.syn && !syn_scan {
+        eprintln!("SYN scan needs root or CAP_NET_RAW, falling back to the TCP connect scan");
     }
 
+    // Octet ranges and exclusions can produce lots of ranges, only list a few of them
+    for (ip_from, ip_to) in scanner.targets().iter().take(10) {
+        if ip_from == ip_to {
+            eprintln!("Scanning IP {:?} on {} TCP ports", ip_from, ports.len());
+        } else {
+            eprintln!("Scanning IP Range {:?} to {:?} on {} TCP ports", ip_from.to_string(), ip_to.to_string(), ports.len());
+        }
+    }
+    if scanner.targets().len() > 10 {
+        eprintln!("... and {} more ranges, {} IPs in total", scanner.targets().len() - 10, scanner.n_targets());
+    }
 
-    
-    // // Test ping
-    // let ip: IpAddr = "1.1.1.1".parse().unwrap();
-    // let ip: IpAddr = "8.8.8.8".parse().unwrap();
-    // let ip: IpAddr = "198.252.206.16".parse().unwrap(); // Stackoverflow
-    // // let ip: IpAddr = "1.2.3.4".parse().unwrap();
-    // println!("Pinging host: {:?}", ip);
-    // let timeout: u32 = 100;
-    // println!("Timeout: {:?}", timeout);
-    // let success_ping = ping_host_syscmd(ip, timeout, true).await;
-    // println!("Status ping {:?} : {:?}", ip, success_ping);
-
-
-    // Test crate surge_ping
-    // let timeout: u32 = 100;
-    // let ip: IpAddr = "0.0.0.0".parse().unwrap(); // ERROR
-    // let ip: IpAddr = "192.168.0.14".parse().unwrap(); // ERROR
-    // let ip: IpAddr = "198.252.206.16".parse().unwrap(); // Stackoverflow
-    // let ipnet: Ipv4Net = "192.168.0.0/28".parse().unwrap(); // Range
-    // let ips: Vec<Ipv4Addr> = ipnet.hosts().collect();
-    
-    // let results = Arc::new(Mutex::new(Vec::new()));
-    // let config = Config::default();
-    // let client: Arc<Client> = Arc::new(Client::new(&config).unwrap());
-    
-    // stream::iter(ips)
-    //     .chunks(10)
-    //     .for_each_concurrent(None, |chunk| {
-    //         let client_clone = Arc::clone(&client);
-    //         let results = Arc::clone(&results);
-    //         async move {
-    //             let mut local_results = Vec::new();
-    //             for ip in chunk {
-    //                 println!("Pinging host: {:?}", ip);
-    //                 let result = ping_host_surge(&client_clone, IpAddr::V4(ip), timeout).await;
-    //                 let local_ping_result = match result {
-    //                     Ok((_packet, _duration)) => {1},
-    //                     Err(_) => {0}
-    //                 };
-    //                 local_results.push(local_ping_result);
-    //             }
-    //             let mut results = results.lock().unwrap();
-    //             results.extend(local_results);
-    //         }
-    //     }).await;
-    // let results = results.lock().unwrap();
-    // println!("Results: {:?}", *results);
-
-    // for ip in ips {
-
-    //     println!("Pinging host: {:?}", ip);
-    //     let result = ping_surge(&client, IpAddr::V4(ip)).await;
-    //     match result {
-    //         Ok((packet, duration)) => {
-    //             // println!("Success: {:?}", packet);
-    //             // println!("Duration: {:?}", duration);
-    //             // // Extract the IP from the packet
-    //             // match packet {
-    //             //     IcmpPacket::V4(packet) => {
-    //             //         let ip = packet.get_source();
-    //             //         println!("Source IP: {:?}", ip);
-    //             //     },
-    //             //     _ => {
-    //             //         println!("Not an Icmpv4Packet");
-    //             //     }
-    //             // }
-    //         },
-    //         Err(_) => {
-    //             // println!("Ping not successful");
-
-    //         }
-    //     }
-    // }
-
-    // // Test IP Range splitting
-    // let ip_start = "192.168.0.1";
-    // let ip_end = "192.168.0.254";
-    // let ip_ranges = split_ip_range(ip_start, ip_end, 10);
-    // // for range in ip_ranges.clone() {
-    // //     println!("IP Range: {:?}", range);
-    // // }
-
-    // // Test IP Range creation on the first range
-    // let ip_list = create_ip_from_range(ip_ranges.clone().into_iter().nth(0).unwrap());
-    // // for ip in ip_list.clone() {
-    // //     println!("IP: {:?}", ip);
-    // // }
-
-    // // Test running sequentially
-    // let ips_to_ping = create_ip_from_range( (String::from("192.168.0.1"), String::from("192.168.0.254")) );
-    // let mut results_concurrent = HashMap::new();
-    // for ip_addr in ips_to_ping {
-    //     let ip: IpAddr = ip_addr.parse().unwrap();
-    //     let timeout: u32 = 100;
-    //     let success_ping = ping_host_syscmd(ip, timeout, false);
-    //     println!("Status ping {:?} : {:?}", ip, success_ping);
-    //     results_concurrent.insert(ip.to_string(), success_ping);
-    // }
-
-
-    // // Test concurrency with tokio
-    // let shared_vector = Arc::new(Mutex::new(Vec::new()));
-    // let mut tasks = vec![];
-    // for range in ip_ranges {
-    //     // Generate IPs for the current range
-    //     let ip_to_check = create_ip_from_range(range);
-    //     let vector = Arc::clone(&shared_vector);
-
-    //     // Spawn a new async task for each IP range
-    //     let task = task::spawn(async move {
-    //         for ip_addr in ip_to_check {
-    //             let ip: IpAddr = ip_addr.parse().unwrap();
-    //             let timeout: u32 = 100;
-
-    //             // Call the async ping function
-    //             let ping_result = ping_host_syscmd(ip, timeout, false).await;
-    //             println!("Status ping {:?} : {:?}", ip, ping_result.status);
-
-    //             // Lock the vector to write the result
-    //             let mut locked_vector = vector.lock().unwrap();
-    //             locked_vector.push(ping_result);
-    //         }
-    //     });
-
-    //     tasks.push(task);
-    // }
-
-    // // Wait for all async tasks to finish
-    // join_all(tasks).await;
+    let progress_bar = ProgressBar::new(scanner.n_targets());
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} ({eta})")
+            .expect("Invalid template format"),
+    );
+
+    // Collect the hosts as they come in
+    let mut results: Vec<PortScanResult> = Vec::new();
+    let mut scan = scanner.scan()?;
+    while let Some(result) = scan.next().await {
+        // Stream the host right away if requested
+        if output_format == OutputFormat::Ndjson {
+            write_ndjson_result(&mut output, &result)?;
+        }
+        results.push(result);
+        progress_bar.inc(1);
+    }
+    progress_bar.finish();
+
+    // Sort by IP
+    results.sort_by_key(|result| result.ip_address);
+
+    let report = ScanReport {
+        metadata: ScanMetadata {
+            command: std::env::args().collect::<Vec<String>>().join(" "),
+            targets: scanner.targets().iter().map(|(ip_from, ip_to)| {
+                if ip_from == ip_to { ip_from.to_string() } else { format!("{}-{}", ip_from, ip_to) }
+            }).collect(),
+            ports: format_port_spec(&ports),
+            udp_ports: args.udp.then(|| format_port_spec(scanner.udp_ports())),
+            syn_scan,
+            timeout_ms: scanner.connect_timeout().as_millis() as u32,
+            start_time,
+            end_time: Utc::now(),
+        },
+        hosts: results,
+    };
 
-    // // Print the results
-    // let locked_vector = shared_vector.lock().unwrap();
-    // println!("Results: {:?}", *locked_vector);
+    // Write the report, ndjson has already been streamed
+    match output_format {
+        OutputFormat::Text => print_results(&mut output, &report)?,
+        OutputFormat::Json => write_json_report(&mut output, &report)?,
+        OutputFormat::Ndjson => output.flush()?,
+        OutputFormat::Csv => write_csv_report(&mut output, &report, args.csv_per_port)?,
+        OutputFormat::Xml => write_xml_report(&mut output, &report)?,
+    }
 
+    Ok(())
 }
\ No newline at end of file
@@ -1,2 +1,11 @@
+pub mod network_arp;
 pub mod network_core;
-pub mod network_helpers;
\ No newline at end of file
+pub mod network_helpers;
+pub mod network_http;
+pub mod network_interfaces;
+pub mod network_oui;
+pub mod network_services;
+pub mod network_syn;
+pub mod network_targets;
+pub mod network_tls;
+pub mod network_udp;
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pnet::datalink::{self, Channel, DataLinkReceiver, DataLinkSender, MacAddr, NetworkInterface};
+use pnet::ipnetwork::IpNetwork;
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::{MutablePacket, Packet};
+
+use tokio::process::Command;
+
+use crate::network::network_targets::TargetSet;
+
+// Ethernet header plus ARP request for IPv4
+const ARP_FRAME_LEN: usize = 42;
+// How often the receiver wakes up to check if it's done
+const ARP_READ_TIMEOUT: Duration = Duration::from_millis(50);
+// Kernel ARP table, the flag marks complete entries
+const PROC_NET_ARP: &str = "/proc/net/arp";
+const ATF_COM: u32 = 0x2;
+
+// Outcome of an ARP sweep over the targets on directly connected Ethernet networks.
+// Hosts answer ARP even if a firewall drops every IP packet, so this is the most reliable discovery on the local network
+#[derive(Debug, Clone, Default)]
+pub struct ArpSweep {
+    // Targets an ARP request was sent to
+    swept: TargetSet,
+    // Hardware address of every target that answered
+    replies: HashMap<Ipv4Addr, MacAddr>,
+}
+
+impl ArpSweep {
+    pub fn swept(&self, ip: IpAddr) -> bool {
+        self.swept.contains(ip)
+    }
+
+    pub fn mac_for(&self, ip: IpAddr) -> Option<MacAddr> {
+        match ip {
+            IpAddr::V4(ipv4) => self.replies.get(&ipv4).copied(),
+            IpAddr::V6(_) => None,
+        }
+    }
+}
+
+// Send an ARP request to every IPv4 target on a local Ethernet network and wait `timeout` for the replies.
+// Blocks, so run it on its own thread. Needs root or CAP_NET_RAW, without them nothing is swept
+pub fn arp_sweep(targets: &TargetSet, timeout: Duration) -> ArpSweep {
+    let mut sweep = ArpSweep::default();
+
+    for interface in datalink::interfaces() {
+        let Some(source_mac) = interface.mac else {
+            continue;
+        };
+        if interface.is_loopback() || !interface.is_up() || source_mac == MacAddr::zero() {
+            continue;
+        }
+
+        for network in interface.ips.iter() {
+            let IpNetwork::V4(network) = network else {
+                continue;
+            };
+            let local = local_targets(targets, network.network(), network.broadcast(), network.ip(), network.prefix());
+            if local.is_empty() {
+                continue;
+            }
+
+            // A missing permission or an interface without a packet socket just means no ARP for this network
+            let Ok(replies) = sweep_interface(&interface, source_mac, network.ip(), &local, timeout) else {
+                continue;
+            };
+            sweep.swept.extend(local.ranges().iter().copied());
+            sweep.replies.extend(replies);
+        }
+    }
+    sweep
+}
+
+// The targets within one network, without the network and broadcast address and without our own address
+fn local_targets(targets: &TargetSet, network: Ipv4Addr, broadcast: Ipv4Addr, own: Ipv4Addr, prefix: u8) -> TargetSet {
+    if prefix >= 32 {
+        return TargetSet::new();
+    }
+    // A /31 is a point to point link, both addresses are hosts
+    let (first, last) = if prefix == 31 {
+        (u32::from(network), u32::from(broadcast))
+    } else {
+        (u32::from(network) + 1, u32::from(broadcast) - 1)
+    };
+
+    let mut local = TargetSet::new();
+    for (start, end) in targets.ranges().iter() {
+        let (IpAddr::V4(start), IpAddr::V4(end)) = (start, end) else {
+            continue;
+        };
+        let start = u32::from(*start).max(first);
+        let end = u32::from(*end).min(last);
+        if start <= end {
+            local.insert_range(IpAddr::V4(Ipv4Addr::from(start)), IpAddr::V4(Ipv4Addr::from(end)));
+        }
+    }
+    local.remove_range(IpAddr::V4(own), IpAddr::V4(own));
+    local
+}
+
+fn sweep_interface(interface: &NetworkInterface, source_mac: MacAddr, source_ip: Ipv4Addr, targets: &TargetSet, timeout: Duration) -> std::io::Result<HashMap<Ipv4Addr, MacAddr>> {
+    let config = datalink::Config {
+        read_timeout: Some(ARP_READ_TIMEOUT),
+        ..Default::default()
+    };
+    let (tx, rx) = match datalink::channel(interface, config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Err(std::io::Error::other("unsupported datalink channel")),
+    };
+
+    // Listen while sending, so no reply is lost when sweeping a large network
+    let (done_sender, done_receiver) = mpsc::channel::<Instant>();
+    let expected = targets.clone();
+    let receiver = thread::spawn(move || receive_replies(rx, &expected, done_receiver, timeout));
+    send_requests(tx, source_mac, source_ip, targets);
+    let _ = done_sender.send(Instant::now());
+
+    receiver.join().map_err(|_| std::io::Error::other("ARP receiver panicked"))
+}
+
+fn send_requests(mut tx: Box<dyn DataLinkSender>, source_mac: MacAddr, source_ip: Ipv4Addr, targets: &TargetSet) {
+    let mut buffer = [0u8; ARP_FRAME_LEN];
+    for target in targets.iter() {
+        let IpAddr::V4(target) = target else {
+            continue;
+        };
+
+        // Fixed size buffers, the packets always fit
+        let mut ethernet = MutableEthernetPacket::new(&mut buffer).unwrap();
+        ethernet.set_destination(MacAddr::broadcast());
+        ethernet.set_source(source_mac);
+        ethernet.set_ethertype(EtherTypes::Arp);
+
+        let mut arp = MutableArpPacket::new(ethernet.payload_mut()).unwrap();
+        arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp.set_protocol_type(EtherTypes::Ipv4);
+        arp.set_hw_addr_len(6);
+        arp.set_proto_addr_len(4);
+        arp.set_operation(ArpOperations::Request);
+        arp.set_sender_hw_addr(source_mac);
+        arp.set_sender_proto_addr(source_ip);
+        arp.set_target_hw_addr(MacAddr::zero());
+        arp.set_target_proto_addr(target);
+
+        let _ = tx.send_to(&buffer, None);
+    }
+}
+
+fn receive_replies(mut rx: Box<dyn DataLinkReceiver>, targets: &TargetSet, done: mpsc::Receiver<Instant>, timeout: Duration) -> HashMap<Ipv4Addr, MacAddr> {
+    let mut replies: HashMap<Ipv4Addr, MacAddr> = HashMap::new();
+    let mut sent_at: Option<Instant> = None;
+
+    loop {
+        // Stop `timeout` after the last request went out, or right away if the sender is gone
+        if sent_at.is_none() {
+            sent_at = match done.try_recv() {
+                Ok(sent_at) => Some(sent_at),
+                Err(mpsc::TryRecvError::Disconnected) => Some(Instant::now()),
+                Err(mpsc::TryRecvError::Empty) => None,
+            };
+        }
+        if sent_at.is_some_and(|sent_at| sent_at.elapsed() >= timeout) {
+            return replies;
+        }
+
+        // Read timeouts show up as errors, just check the clock again
+        let Ok(frame) = rx.next() else {
+            continue;
+        };
+        let Some(ethernet) = EthernetPacket::new(frame) else {
+            continue;
+        };
+        if ethernet.get_ethertype() != EtherTypes::Arp {
+            continue;
+        }
+        if let Some(arp) = ArpPacket::new(ethernet.payload()) {
+            // Ignore gratuitous ARP and replies to requests of other hosts
+            if arp.get_operation() == ArpOperations::Reply && targets.contains(IpAddr::V4(arp.get_sender_proto_addr())) {
+                replies.insert(arp.get_sender_proto_addr(), arp.get_sender_hw_addr());
+            }
+        }
+    }
+}
+
+// Hardware address of a host on a local network from the kernel neighbour table. The probes of the scan
+// fill it through ARP (IPv4) and neighbour discovery (IPv6), so this works without any privileges.
+// Reads /proc/net/arp and `ip -6 neigh`, which only exist on Linux
+pub async fn neighbour_mac(ip: IpAddr) -> Option<String> {
+    let mac = match ip {
+        IpAddr::V4(ipv4) => {
+            let table = tokio::fs::read_to_string(PROC_NET_ARP).await.ok()?;
+            arp_table_mac(&table, ipv4)?
+        },
+        IpAddr::V6(_) => {
+            // e.g. "fd00::1 dev eth0 lladdr 02:fc:00:00:00:05 REACHABLE", failed entries have no lladdr
+            let output = Command::new("ip").args(["-6", "neigh", "show", "to", &ip.to_string()]).output().await.ok()?;
+            let text = String::from_utf8_lossy(&output.stdout);
+            let mut words = text.split_whitespace();
+            words.find(|word| *word == "lladdr")?;
+            words.next()?.to_string()
+        },
+    };
+    (mac.parse::<MacAddr>().ok()? != MacAddr::zero()).then_some(mac)
+}
+
+// Complete entry of `ip` in the text of /proc/net/arp
+fn arp_table_mac(table: &str, ip: Ipv4Addr) -> Option<String> {
+    // IP address, HW type, Flags, HW address, Mask, Device
+    table.lines().skip(1).find_map(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let flags = u32::from_str_radix(columns.get(2)?.trim_start_matches("0x"), 16).ok()?;
+        let mac = columns.get(3)?;
+        (columns.first()?.parse::<Ipv4Addr>().ok()? == ip && flags & ATF_COM != 0).then(|| mac.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    // Replays captured frames, then behaves like a read timeout
+    struct Replay {
+        frames: Vec<Vec<u8>>,
+        next: usize,
+    }
+
+    impl DataLinkReceiver for Replay {
+        fn next(&mut self) -> io::Result<&[u8]> {
+            self.next += 1;
+            match self.frames.get(self.next - 1) {
+                Some(frame) => Ok(frame),
+                None => {
+                    thread::sleep(Duration::from_millis(5));
+                    Err(io::Error::from(io::ErrorKind::TimedOut))
+                },
+            }
+        }
+    }
+
+    fn arp_frame(operation: pnet::packet::arp::ArpOperation, sender_ip: &str, sender_mac: MacAddr) -> Vec<u8> {
+        let mut buffer = vec![0u8; ARP_FRAME_LEN];
+        let mut ethernet = MutableEthernetPacket::new(&mut buffer).unwrap();
+        ethernet.set_ethertype(EtherTypes::Arp);
+        let mut arp = MutableArpPacket::new(ethernet.payload_mut()).unwrap();
+        arp.set_operation(operation);
+        arp.set_sender_hw_addr(sender_mac);
+        arp.set_sender_proto_addr(sender_ip.parse().unwrap());
+        buffer
+    }
+
+    #[test]
+    fn local_targets_keeps_the_hosts_of_the_network_without_ourselves() {
+        let mut targets = TargetSet::new();
+        targets.insert_range(ip("192.168.0.0"), ip("192.168.1.10"));
+        targets.insert_range(ip("fd00::1"), ip("fd00::1"));
+
+        let local = local_targets(&targets, "192.168.0.0".parse().unwrap(), "192.168.0.255".parse().unwrap(), "192.168.0.7".parse().unwrap(), 24);
+        assert_eq!(local.ranges(), &[(ip("192.168.0.1"), ip("192.168.0.6")), (ip("192.168.0.8"), ip("192.168.0.254"))]);
+    }
+
+    #[test]
+    fn local_targets_of_point_to_point_links() {
+        let mut targets = TargetSet::new();
+        targets.insert_range(ip("10.0.0.0"), ip("10.0.0.255"));
+        let network: Ipv4Addr = "10.0.0.4".parse().unwrap();
+        let broadcast: Ipv4Addr = "10.0.0.5".parse().unwrap();
+
+        assert_eq!(local_targets(&targets, network, broadcast, network, 31).ranges(), &[(ip("10.0.0.5"), ip("10.0.0.5"))]);
+        assert!(local_targets(&targets, network, network, network, 32).is_empty());
+    }
+
+    #[test]
+    fn receive_replies_only_takes_replies_from_targets() {
+        let mac = MacAddr::new(0x52, 0x54, 0x00, 0x12, 0x34, 0x56);
+        let other = MacAddr::new(0x02, 0, 0, 0, 0, 1);
+        let frames = vec![
+            arp_frame(ArpOperations::Reply, "192.168.0.10", mac),
+            // A request, a reply of a host that is no target and a frame that is no ARP at all
+            arp_frame(ArpOperations::Request, "192.168.0.11", other),
+            arp_frame(ArpOperations::Reply, "192.168.0.200", other),
+            vec![0u8; 60],
+        ];
+        let mut targets = TargetSet::new();
+        targets.insert_range(ip("192.168.0.1"), ip("192.168.0.20"));
+
+        let (done_sender, done_receiver) = mpsc::channel();
+        done_sender.send(Instant::now()).unwrap();
+        let replies = receive_replies(Box::new(Replay { frames, next: 0 }), &targets, done_receiver, Duration::from_millis(100));
+        assert_eq!(replies, HashMap::from([("192.168.0.10".parse().unwrap(), mac)]));
+    }
+
+    #[test]
+    fn arp_table_mac_takes_complete_entries_only() {
+        let table = "IP address       HW type     Flags       HW address            Mask     Device\n\
+            192.168.0.1      0x1         0x2         b8:27:eb:12:34:56     *        eth0\n\
+            192.168.0.2      0x1         0x0         00:00:00:00:00:00     *        eth0\n\
+            192.168.0.3      0x1         0x2\n";
+        assert_eq!(arp_table_mac(table, "192.168.0.1".parse().unwrap()).as_deref(), Some("b8:27:eb:12:34:56"));
+        assert_eq!(arp_table_mac(table, "192.168.0.2".parse().unwrap()), None);
+        // A line cut short must not panic
+        assert_eq!(arp_table_mac(table, "192.168.0.3".parse().unwrap()), None);
+        assert_eq!(arp_table_mac("", "192.168.0.1".parse().unwrap()), None);
+    }
+}
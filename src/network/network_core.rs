@@ -1,20 +1,63 @@
-#[allow(dead_code)]
+use std::time::{Duration, Instant};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-use pnet::datalink::{self, NetworkInterface};
+use serde::{Serialize, Deserialize};
 
-use std::time::Duration;
-// use std::process::Command; // Used for ping via systemcommand
-use std::net::{IpAddr, Ipv4Addr, TcpStream};
+use crate::error::ScanError;
+use crate::network::network_http::HttpInfo;
+use crate::network::network_services::ServiceInfo;
+use crate::network::network_tls::TlsInfo;
 
-use serde::{Serialize, Deserialize};
+use futures::stream::{self, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::task;
 
-use std::sync::{Arc};
-use surge_ping::{Client, PingIdentifier, PingSequence};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use surge_ping::{Client, Config, IcmpPacket, PingIdentifier, PingSequence, ICMP};
+use socket2::Type;
 use rand::random;
 use dns_lookup::lookup_addr;
+use thiserror::Error;
 
-const TCP_PORTS: [u16; 11] = [20,21,22,23,25,53,80,110,143,443,445];
+// Ports scanned if the user does not select any
+pub const TCP_PORTS: [u16; 11] = [20,21,22,23,25,53,80,110,143,443,445];
+// The 100 most common TCP ports according to nmap-services, the same set `nmap -F` scans
+pub const TOP_100_TCP_PORTS: [u16; 100] = [
+    7,9,13,21,22,23,25,26,37,53,79,80,81,88,106,110,111,113,119,135,139,143,144,179,199,
+    389,427,443,444,445,465,513,514,515,543,544,548,554,587,631,646,873,990,993,995,
+    1025,1026,1027,1028,1029,1110,1433,1720,1723,1755,1900,2000,2001,2049,2121,2717,
+    3000,3128,3306,3389,3986,4899,5000,5009,5051,5060,5101,5190,5357,5432,5631,5666,
+    5800,5900,6000,6001,6646,7070,8000,8008,8009,8080,8081,8443,8888,9100,9999,10000,
+    32768,49152,49153,49154,49155,49156,49157,
+];
 const PING_PAYLOAD: [u8; 8] = [0; 8];
+// One echo per host like nmap, answered within the surge-ping default timeout
+pub const DEFAULT_PING_COUNT: u16 = 1;
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(2);
+// Hostname lookups through the OS resolver, which has no timeout of its own
+pub const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(2);
+// Threads of the blocking pool the OS resolver may tie up at the same time
+const MAX_RESOLVER_THREADS: usize = 16;
+// The built-in DNS client waits this long for every single answer and asks every server this many more times
+pub const DEFAULT_DNS_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+pub const DEFAULT_DNS_RETRIES: usize = 2;
+pub const DNS_PORT: u16 = 53;
+// Without EDNS answers over UDP are at most 512 bytes, longer ones come over TCP
+const MAX_DNS_MESSAGE_LEN: usize = 65535;
+// Compressed names point to earlier names, bound the jumps so a malicious answer can't loop forever
+const MAX_DNS_NAME_JUMPS: usize = 16;
+// Bounds of the timeouts derived from measured round trip times, like nmap's --min-rtt-timeout and --max-rtt-timeout
+pub const MIN_ADAPTIVE_TIMEOUT: Duration = Duration::from_millis(50);
+pub const MAX_ADAPTIVE_TIMEOUT: Duration = Duration::from_secs(10);
+// Default ports of the TCP discovery probes, the same ones unprivileged nmap connects to
+pub const TCP_PING_PORTS: [u16; 2] = [80, 443];
+// Upper bound of simultaneously open TCP connect probes per host, on top of the global limit
+const MAX_PORTS_IN_FLIGHT: usize = 64;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Status {
@@ -22,175 +65,1276 @@ pub enum Status {
     Down,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+// How the liveliness of a host was checked
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DiscoveryMethod {
+    // ARP request, only for targets on a directly connected Ethernet network
+    Arp,
+    // ICMP echo on a raw socket, needs root or CAP_NET_RAW
+    IcmpRaw,
+    // ICMP echo on an unprivileged datagram socket, allowed on Linux for the groups in net.ipv4.ping_group_range
+    IcmpDatagram,
+    // TCP connect to the discovery ports. Used on request and if no ICMP socket is permitted at all
+    TcpConnect,
+}
+
+// Why a host is reported up or down, like the reason of nmap. The TCP and UDP reasons carry the port that answered
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Reason {
+    ArpResponse,
+    EchoReply,
+    SynAck(u16),
+    ConnRefused(u16),
+    // RST to a SYN of the SYN scan
+    Reset(u16),
+    UdpResponse(u16),
+    PortUnreach(u16),
+    // Discovery disabled, the host is assumed to be up
+    UserSet,
+    NoResponse,
+}
+
+impl Reason {
+    // nmap's name of the reason
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reason::ArpResponse => "arp-response",
+            Reason::EchoReply => "echo-reply",
+            Reason::SynAck(_) => "syn-ack",
+            Reason::ConnRefused(_) => "conn-refused",
+            Reason::Reset(_) => "reset",
+            Reason::UdpResponse(_) => "udp-response",
+            Reason::PortUnreach(_) => "port-unreach",
+            Reason::UserSet => "user-set",
+            Reason::NoResponse => "no-response",
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Reason::SynAck(port) | Reason::ConnRefused(port) | Reason::Reset(port) | Reason::UdpResponse(port) | Reason::PortUnreach(port) => Some(*port),
+            _ => None,
+        }
+    }
+}
+
+// How many echo requests are sent to every host and how long to wait for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PingOptions {
+    pub count: u16,
+    // Time between two echo requests to the same host
+    pub interval: Duration,
+    // Time to wait for the reply to a single echo request
+    pub timeout: Duration,
+}
+
+impl Default for PingOptions {
+    fn default() -> Self {
+        PingOptions {
+            count: DEFAULT_PING_COUNT,
+            interval: DEFAULT_PING_INTERVAL,
+            timeout: DEFAULT_PING_TIMEOUT,
+        }
+    }
+}
+
+// Summary of the echo replies of one host, like the last lines of `ping`.
+// Round trip times are in milliseconds and None if no reply came back
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PingStats {
+    pub transmitted: u16,
+    pub received: u16,
+    // Lost echo requests in percent
+    pub packet_loss: f64,
+    pub rtt_min_ms: Option<f64>,
+    pub rtt_avg_ms: Option<f64>,
+    pub rtt_max_ms: Option<f64>,
+    pub rtt_stddev_ms: Option<f64>,
+    // TTL of the first reply, only known for IPv4
+    pub ttl: Option<u8>,
+}
+
+impl PingStats {
+    fn from_replies(transmitted: u16, rtts: &[Duration], ttl: Option<u8>) -> Self {
+        let received = rtts.len() as u16;
+        let packet_loss = if transmitted == 0 {
+            0.0
+        } else {
+            round_ms(100.0 * (transmitted - received) as f64 / transmitted as f64)
+        };
+        let rtts_ms: Vec<f64> = rtts.iter().map(|rtt| rtt.as_micros() as f64 / 1000.0).collect();
+        if rtts_ms.is_empty() {
+            return PingStats { transmitted, received, packet_loss, rtt_min_ms: None, rtt_avg_ms: None, rtt_max_ms: None, rtt_stddev_ms: None, ttl };
+        }
+
+        // Population standard deviation, the same as the mdev of `ping`
+        let avg = rtts_ms.iter().sum::<f64>() / rtts_ms.len() as f64;
+        let variance = rtts_ms.iter().map(|rtt| (rtt - avg).powi(2)).sum::<f64>() / rtts_ms.len() as f64;
+        PingStats {
+            transmitted,
+            received,
+            packet_loss,
+            rtt_min_ms: rtts_ms.iter().copied().reduce(f64::min),
+            rtt_avg_ms: Some(round_ms(avg)),
+            rtt_max_ms: rtts_ms.iter().copied().reduce(f64::max),
+            rtt_stddev_ms: Some(round_ms(variance.sqrt())),
+            ttl,
+        }
+    }
+}
+
+// Keep microsecond precision, more digits are only float noise
+fn round_ms(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+// Smoothed round trip time and its variation like the retransmission timer of TCP (RFC 6298).
+// A probe is given up after srtt + 4 * rttvar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RttEstimate {
+    srtt_ms: f64,
+    rttvar_ms: f64,
+}
+
+impl RttEstimate {
+    pub fn new(rtt: Duration) -> Self {
+        let rtt_ms = rtt.as_secs_f64() * 1000.0;
+        RttEstimate { srtt_ms: rtt_ms, rttvar_ms: rtt_ms / 2.0 }
+    }
+
+    // Estimate for a single host from its echo replies, None if it did not answer
+    pub fn from_ping(ping: &PingStats) -> Option<Self> {
+        let avg = ping.rtt_avg_ms?;
+        let stddev = ping.rtt_stddev_ms.unwrap_or(0.0);
+        Some(RttEstimate { srtt_ms: avg, rttvar_ms: stddev.max(avg / 2.0) })
+    }
+
+    pub fn update(&mut self, rtt: Duration) {
+        let rtt_ms = rtt.as_secs_f64() * 1000.0;
+        self.rttvar_ms = 0.75 * self.rttvar_ms + 0.25 * (self.srtt_ms - rtt_ms).abs();
+        self.srtt_ms = 0.875 * self.srtt_ms + 0.125 * rtt_ms;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs_f64((self.srtt_ms + 4.0 * self.rttvar_ms) / 1000.0).clamp(MIN_ADAPTIVE_TIMEOUT, MAX_ADAPTIVE_TIMEOUT)
+    }
+}
+
+// Scan wide round trip time estimate, fed by every echo reply and every answered TCP connect
+#[derive(Debug, Default)]
+pub struct AdaptiveTimeout {
+    estimate: Mutex<Option<RttEstimate>>,
+}
+
+impl AdaptiveTimeout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&self, rtt: Duration) {
+        let mut estimate = self.estimate.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match estimate.as_mut() {
+            Some(estimate) => estimate.update(rtt),
+            None => *estimate = Some(RttEstimate::new(rtt)),
+        }
+    }
+
+    // None until the first round trip time was measured
+    pub fn estimate(&self) -> Option<RttEstimate> {
+        *self.estimate.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// State of a probed port, named like in nmap
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PortState {
+    Open,
+    // No answer at all, either nothing listens or a firewall drops the probes. Only for UDP
+    OpenFiltered,
+    // The host answered that nothing listens, with a TCP RST or an ICMP port unreachable
+    Closed,
+    // No answer to a SYN or a connect, a firewall drops the probes
+    Filtered,
+    // The probe failed without an answer from the port, e.g. the host is unreachable
+    Error(ProbeError),
+}
+
+impl PortState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PortState::Open => "open",
+            PortState::OpenFiltered => "open|filtered",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::Error(_) => "error",
+        }
+    }
+}
+
+// Why a port probe failed, from the error of the connect
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProbeError {
+    // ICMP host or network unreachable, or no route at all
+    HostUnreachable,
+    NetworkUnreachable,
+    // A local firewall rejected the packet
+    PermissionDenied,
+    // No local address or port left for the connection
+    AddressNotAvailable,
+    Other,
+}
+
+impl ProbeError {
+    // nmap's reason for the failed probe where it has one
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProbeError::HostUnreachable => "host-unreach",
+            ProbeError::NetworkUnreachable => "net-unreach",
+            ProbeError::PermissionDenied => "admin-prohibited",
+            ProbeError::AddressNotAvailable => "addr-not-available",
+            ProbeError::Other => "error",
+        }
+    }
+}
+
+impl From<ErrorKind> for ProbeError {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::HostUnreachable => ProbeError::HostUnreachable,
+            ErrorKind::NetworkUnreachable => ProbeError::NetworkUnreachable,
+            ErrorKind::PermissionDenied => ProbeError::PermissionDenied,
+            ErrorKind::AddrNotAvailable => ProbeError::AddressNotAvailable,
+            _ => ProbeError::Other,
+        }
+    }
+}
+
+// What is known about a single port
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PortInfo {
+    pub port: u16,
+    pub state: PortState,
+    // Only with service detection, None if the port did not answer any probe
+    pub service: Option<ServiceInfo>,
+    // Only with TLS inspection, None if the port did not complete a handshake
+    pub tls: Option<TlsInfo>,
+    // Only with HTTP fingerprinting, None if the port did not answer `GET /`
+    pub http: Option<HttpInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct PortScanResult {
-    pub ip_address: Ipv4Addr,
+    pub ip_address: IpAddr,
     pub status: Status,
-    pub hostname: String,
+    pub reason: Reason,
+    // Every discovery probe sent to the host, empty if discovery was disabled
+    pub discovery: Vec<DiscoveryMethod>,
+    // Hardware address from the ARP reply or the kernel neighbour table, only known for hosts on a local network
+    pub mac_address: Option<String>,
+    // Manufacturer of the network adapter according to the OUI of the MAC address
+    pub vendor: Option<String>,
+    // Name from the reverse DNS lookup, None if it failed or was not done
+    pub hostname: Option<String>,
+    // Why the reverse DNS lookup failed, None if it succeeded or was not done
+    pub dns_error: Option<DnsError>,
+    // TXT records of the hostname, only looked up on request with the built-in DNS client
+    pub dns_txt: Vec<String>,
     pub open_tcp_ports: Vec<u16>,
+    // Every probed TCP port with its state and details, sorted by port
+    pub tcp_ports: Vec<PortInfo>,
+    // Every probed UDP port with its state, empty without UDP scan
+    pub udp_ports: Vec<PortInfo>,
+    // Average round trip time of the pings in milliseconds, None if the host did not answer
+    pub rtt_ms: Option<f64>,
+    // Echo statistics, None if the host was not pinged
+    pub ping: Option<PingStats>,
 }
 
 impl PortScanResult {
-    fn new(ip_address: Ipv4Addr, status: Status, hostname: String, open_tcp_ports: Vec<u16>) -> Self {
+    pub fn new(ip_address: IpAddr, status: Status, reason: Reason, discovery: Vec<DiscoveryMethod>, tcp_ports: Vec<(u16, PortState)>, ping: Option<PingStats>) -> Self {
         PortScanResult {
             ip_address,
-            status: status.clone(),
-            hostname: hostname,
-            open_tcp_ports: open_tcp_ports,
+            status,
+            reason,
+            discovery,
+            mac_address: None,
+            vendor: None,
+            hostname: None,
+            dns_error: None,
+            dns_txt: Vec::new(),
+            open_tcp_ports: tcp_ports.iter().filter(|(_, state)| *state == PortState::Open).map(|(port, _)| *port).collect(),
+            tcp_ports: tcp_ports.into_iter().map(|(port, state)| PortInfo { port, state, service: None, tls: None, http: None }).collect(),
+            udp_ports: Vec::new(),
+            rtt_ms: ping.as_ref().and_then(|ping| ping.rtt_avg_ms),
+            ping,
+        }
+    }
+
+    pub fn with_mac_address(mut self, mac_address: Option<String>, vendor: Option<String>) -> Self {
+        self.mac_address = mac_address;
+        self.vendor = vendor;
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: Option<String>, dns_error: Option<DnsError>) -> Self {
+        self.hostname = hostname;
+        self.dns_error = dns_error;
+        self
+    }
+
+    pub fn with_dns_txt(mut self, dns_txt: Vec<String>) -> Self {
+        self.dns_txt = dns_txt;
+        self
+    }
+
+    pub fn with_udp_ports(mut self, udp_ports: Vec<PortInfo>) -> Self {
+        self.udp_ports = udp_ports;
+        self
+    }
+
+    // Attach the services found on the open ports
+    pub fn with_services(mut self, services: Vec<(u16, ServiceInfo)>) -> Self {
+        for (port, service) in services {
+            if let Some(info) = self.tcp_ports.iter_mut().find(|info| info.port == port) {
+                info.service = Some(service);
+            }
         }
+        self
+    }
+
+    // Attach the outcome of the TLS handshakes with the open ports
+    pub fn with_tls(mut self, tls: Vec<(u16, TlsInfo)>) -> Self {
+        for (port, tls) in tls {
+            if let Some(info) = self.tcp_ports.iter_mut().find(|info| info.port == port) {
+                info.tls = Some(tls);
+            }
+        }
+        self
+    }
+
+    // Attach the fingerprints of the web servers on the open ports
+    pub fn with_http(mut self, http: Vec<(u16, HttpInfo)>) -> Self {
+        for (port, http) in http {
+            if let Some(info) = self.tcp_ports.iter_mut().find(|info| info.port == port) {
+                info.http = Some(http);
+            }
+        }
+        self
     }
 }
 
-/* DEPRECATED PING via systemcommand
+// surge-ping sockets speak either ICMPv4 or ICMPv6, so there is one client per address family.
+// A family without a client is not permitted to ping and falls back to the TCP discovery probes
+#[derive(Clone)]
+pub struct PingClients {
+    v4: Option<Arc<Client>>,
+    v6: Option<Arc<Client>>,
+}
 
-fn create_ping_command(ip_str: &String, timeout: u32) -> String {
-    if cfg!(target_os = "windows") {
-        format!(
-            "ping {} -n 4 -w {} >nul && exit 0 || exit 1",
-            ip_str,
-            timeout
-        )
-    } else if cfg!(target_os = "macos") {
-        format!(
-            "ping -c 2 {} >/dev/null && exit 0 || exit 1",
-            ip_str
-        )
-    } else {
-        panic!("Unsupported OS detected");
+impl PingClients {
+    pub fn new(ipv4: bool, ipv6: bool) -> Result<Self, ScanError> {
+        // Only open the sockets we need, a host without IPv6 can still scan IPv4 targets
+        let v4 = if ipv4 {
+            create_client(ICMP::V4)?.map(Arc::new)
+        } else {
+            None
+        };
+        let v6 = if ipv6 {
+            create_client(ICMP::V6)?.map(Arc::new)
+        } else {
+            None
+        };
+        Ok(PingClients { v4, v6 })
+    }
+
+    fn for_ip(&self, ip: IpAddr) -> Option<&Arc<Client>> {
+        match ip {
+            IpAddr::V4(_) => self.v4.as_ref(),
+            IpAddr::V6(_) => self.v6.as_ref(),
+        }
+    }
+
+    // Kind of ICMP socket used for this address family, None if ICMP is not permitted
+    pub fn method_for(&self, ip: IpAddr) -> Option<DiscoveryMethod> {
+        match self.for_ip(ip) {
+            Some(client) if client.get_socket().get_type() == Type::RAW => Some(DiscoveryMethod::IcmpRaw),
+            Some(_) => Some(DiscoveryMethod::IcmpDatagram),
+            None => None,
+        }
     }
 }
 
-fn determine_ping_parameters() -> (String, String) {
-    if cfg!(target_os = "windows") {
-        (String::from("cmd"), String::from("/C"))
-    } else if cfg!(target_os = "macos") {
-        (String::from("sh"), String::from("-c"))
-    } else {
-        panic!("Unsupported OS detected");
+fn create_client(kind: ICMP) -> Result<Option<Client>, ScanError> {
+    // Prefer a raw socket, it also reads the TTL of the replies. If it's not permitted,
+    // surge-ping retries with an unprivileged datagram socket on its own
+    match Client::new(&Config::builder().kind(kind).sock_type_hint(Type::RAW).build()) {
+        Ok(client) => Ok(Some(client)),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => Ok(None),
+        Err(e) => Err(ScanError::Io(e)),
     }
 }
 
-pub async fn ping_host_syscmd(ip: Ipv4Addr, timeout: u32, verboose: bool) -> PortScanResult {
+pub async fn scan_ports_tcp(ip: IpAddr, timeout: Duration, ports: &[u16], permits: &Semaphore, adaptive: Option<&AdaptiveTimeout>) -> Vec<(u16, PortState)> {
+    // Probe all ports concurrently on non-blocking sockets, with at most MAX_PORTS_IN_FLIGHT connects pending.
+    // Every connect also needs one of the permits shared by all hosts of the scan
+    let mut states: Vec<(u16, PortState)> = stream::iter(ports.iter().copied())
+        .map(|port| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            let address = SocketAddr::new(ip, port);
+            let start = Instant::now();
+            // An accepted connection means open, a RST closed and silence filtered, other errors say nothing about the port
+            let state = match tokio::time::timeout(timeout, TcpStream::connect(address)).await {
+                Ok(Ok(_)) => PortState::Open,
+                Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => PortState::Closed,
+                Ok(Err(e)) => PortState::Error(e.kind().into()),
+                Err(_) => PortState::Filtered,
+            };
+            // Both a SYN-ACK and a RST take one round trip, use them to tune the timeouts
+            if let (Some(adaptive), PortState::Open | PortState::Closed) = (adaptive, state) {
+                adaptive.observe(start.elapsed());
+            }
+            Some((port, state))
+        })
+        .buffer_unordered(MAX_PORTS_IN_FLIGHT)
+        .filter_map(|state| async move { state })
+        .collect()
+        .await;
 
-        // ip to String
-        let ip_str = ip.to_string();
-    
-        if verboose {
-            println!("Pinging host: {:?}", ip);
-            println!("Timeout: {:?}", timeout);
+    // Completion order is arbitrary, report the ports sorted
+    states.sort_unstable_by_key(|(port, _)| *port);
+    states
+}
+
+pub async fn ping_host_tcp(ip: IpAddr, timeout: Duration, ports: &[u16], permits: &Semaphore, adaptive: Option<&AdaptiveTimeout>) -> Option<Reason> {
+    // Discovery without ICMP: a host that accepts or refuses a connection is up,
+    // only a timeout or an unreachable network count as no answer
+    let mut replies = stream::iter(ports.iter().copied())
+        .map(|port| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            let start = Instant::now();
+            let reason = match tokio::time::timeout(timeout, TcpStream::connect(SocketAddr::new(ip, port))).await {
+                Ok(Ok(_)) => Some(Reason::SynAck(port)),
+                Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => Some(Reason::ConnRefused(port)),
+                _ => None,
+            };
+            if let (Some(_), Some(adaptive)) = (reason, adaptive) {
+                adaptive.observe(start.elapsed());
+            }
+            reason
+        })
+        .buffer_unordered(MAX_PORTS_IN_FLIGHT);
+
+    // The first answer is enough, the remaining probes are dropped
+    while let Some(reply) = replies.next().await {
+        if reply.is_some() {
+            return reply;
         }
-    
-        // Determine OS and fetch command for ping
-        let command: String = create_ping_command(&ip_str, timeout);
-        
-        if verboose {
-            println!("Command: {}", command);
+    }
+    None
+}
+
+// Why a DNS lookup did not return a name or an address
+#[derive(Debug, Clone, Error, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DnsError {
+    // The name doesn't exist (NXDOMAIN) or has no record of the type
+    #[error("no record")]
+    NotFound,
+    #[error("timeout")]
+    Timeout,
+    // Message of the resolver, e.g. "Name or service not known" if the address has no PTR record
+    #[error("{0}")]
+    Failed(String),
+}
+
+// Reverse lookup of the hostname, with the built-in DNS client if there is one, otherwise with the OS resolver.
+// The timeout only bounds the OS resolver. The client is bounded by its per-query timeout and retries, cutting it
+// short with another timeout would cancel the retries and the next servers
+pub async fn reverse_dns_lookup(ip: IpAddr, timeout: Duration, client: Option<&DnsClient>, resolver_permits: &Arc<Semaphore>) -> Result<String, DnsError> {
+    if let Some(client) = client {
+        return client.reverse_lookup(ip).await;
+    }
+
+    // The OS resolver blocks, run it on the blocking pool so it can't stall the pings and connects.
+    // A lookup that takes too long is abandoned but holds its permit until the resolver gives up,
+    // so a hanging resolver ties up a few threads at most, however many addresses are looked up
+    let lookup = async {
+        let permit = Arc::clone(resolver_permits).acquire_owned().await.map_err(|_| DnsError::Failed(String::from("resolver closed")))?;
+        task::spawn_blocking(move || {
+            let name = lookup_addr(&ip);
+            drop(permit);
+            name
+        })
+        .await
+        .map_err(|_| DnsError::Failed(String::from("resolver thread failed")))
+    };
+    match tokio::time::timeout(timeout, lookup).await {
+        Ok(Ok(Ok(name))) => Ok(name),
+        Ok(Ok(Err(e))) => Err(DnsError::Failed(e.to_string())),
+        // No permit or the thread died without an answer, e.g. it panicked inside the resolver
+        Ok(Err(error)) => Err(error),
+        Err(_) => Err(DnsError::Timeout),
+    }
+}
+
+// Reverse lookups already done, by address, so scanning an address again doesn't ask the resolver again.
+// Timeouts are not cached, the next attempt may well get an answer
+#[derive(Debug)]
+pub struct DnsCache {
+    entries: Mutex<HashMap<IpAddr, Result<String, DnsError>>>,
+    // Lookups of the OS resolver running at the same time, abandoned ones included
+    resolver_permits: Arc<Semaphore>,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        DnsCache {
+            entries: Mutex::new(HashMap::new()),
+            resolver_permits: Arc::new(Semaphore::new(MAX_RESOLVER_THREADS)),
         }
-    
-        let (shell, flag) = determine_ping_parameters();
-    
-        let status = Command::new(shell)
-            .args([flag, command])
-            .status()
-            .expect("Failed to execute command");
-    
-        // Get hostname
-        let hostname = match lookup_addr(&IpAddr::from(ip)) {
-            Ok(name) => name,
-            Err(_) => String::from("Unknown"),
+    }
+}
+
+impl DnsCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn reverse_lookup(&self, ip: IpAddr, timeout: Duration, client: Option<&DnsClient>) -> Result<String, DnsError> {
+        if let Some(entry) = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&ip) {
+            return entry.clone();
+        }
+        let lookup = reverse_dns_lookup(ip, timeout, client, &self.resolver_permits).await;
+        if lookup != Err(DnsError::Timeout) {
+            self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(ip, lookup.clone());
+        }
+        lookup
+    }
+}
+
+// Record types the built-in DNS client asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Ptr,
+    Txt,
+}
+
+impl RecordType {
+    fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ptr => 12,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+        }
+    }
+}
+
+// Data of an answer record. Names are without the trailing dot, the strings of a TXT record are joined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Txt(String),
+}
+
+// Minimal stub resolver that asks the given nameservers directly instead of going through the OS resolver,
+// e.g. for internal zones the system resolver doesn't know. Queries go over UDP, truncated answers are repeated over TCP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsClient {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+    retries: usize,
+}
+
+impl DnsClient {
+    pub fn new(servers: Vec<SocketAddr>) -> Self {
+        DnsClient { servers, timeout: DEFAULT_DNS_QUERY_TIMEOUT, retries: DEFAULT_DNS_RETRIES }
+    }
+
+    // Time to wait for the answer to a single query
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // How many more times a server that doesn't answer is asked before the next one is tried
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn servers(&self) -> &[SocketAddr] {
+        &self.servers
+    }
+
+    // Hostname of the address from its PTR record in in-addr.arpa or ip6.arpa
+    pub async fn reverse_lookup(&self, ip: IpAddr) -> Result<String, DnsError> {
+        self.query(&reverse_name(ip), RecordType::Ptr).await?
+            .into_iter()
+            .find_map(|record| match record {
+                Record::Ptr(name) => Some(name),
+                _ => None,
+            })
+            .ok_or(DnsError::NotFound)
+    }
+
+    // Every IPv4 and IPv6 address of the name. Fails only if neither kind of address was found
+    pub async fn lookup_host(&self, name: &str) -> Result<Vec<IpAddr>, DnsError> {
+        let (ipv4, ipv6) = tokio::join!(self.query(name, RecordType::A), self.query(name, RecordType::Aaaa));
+        let addresses: Vec<IpAddr> = ipv4.iter().chain(ipv6.iter())
+            .flatten()
+            .filter_map(|record| match record {
+                Record::A(ip) => Some(IpAddr::V4(*ip)),
+                Record::Aaaa(ip) => Some(IpAddr::V6(*ip)),
+                _ => None,
+            })
+            .collect();
+        match (addresses.is_empty(), ipv4, ipv6) {
+            (false, _, _) => Ok(addresses),
+            // A name without addresses of one family is normal, report the more telling error
+            (true, Err(error), Err(DnsError::NotFound)) | (true, Err(DnsError::NotFound), Err(error)) => Err(error),
+            (true, Err(error), _) | (true, _, Err(error)) => Err(error),
+            (true, Ok(_), Ok(_)) => Err(DnsError::NotFound),
+        }
+    }
+
+    pub async fn lookup_txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        Ok(self.query(name, RecordType::Txt).await?
+            .into_iter()
+            .filter_map(|record| match record {
+                Record::Txt(text) => Some(text),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Ask the servers one after the other, each one again up to `retries` times if it doesn't answer.
+    // An answer ends the query, also one that the name doesn't exist. Only silence and server errors move on to the next server
+    pub async fn query(&self, name: &str, record_type: RecordType) -> Result<Vec<Record>, DnsError> {
+        let mut last_error = DnsError::Failed(String::from("no DNS server"));
+        for server in self.servers.iter() {
+            for _ in 0..=self.retries {
+                match self.query_server(*server, name, record_type).await {
+                    Ok(records) => return Ok(records),
+                    Err(DnsError::NotFound) => return Err(DnsError::NotFound),
+                    Err(DnsError::Timeout) => last_error = DnsError::Timeout,
+                    // A server that answered with an error won't change its mind
+                    Err(error) => {
+                        last_error = error;
+                        break;
+                    },
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn query_server(&self, server: SocketAddr, name: &str, record_type: RecordType) -> Result<Vec<Record>, DnsError> {
+        let id: u16 = random();
+        let query = encode_dns_query(id, name, record_type)
+            .ok_or_else(|| DnsError::Failed(format!("invalid name '{}'", name)))?;
+        let failed = |e: std::io::Error| DnsError::Failed(e.to_string());
+
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
         };
-    
-        // Scan common TCP ports
-        let open_tcp_ports: Vec<u16> = scan_ports_tcp(ip, Duration::from_millis(timeout as u64), &TCP_PORTS);
-    
-        if status.success() {
-            if verboose {
-                println!("Ping successful");
+        let socket = UdpSocket::bind(local).await.map_err(failed)?;
+        socket.connect(server).await.map_err(failed)?;
+        socket.send(&query).await.map_err(failed)?;
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let mut buffer = vec![0u8; MAX_DNS_MESSAGE_LEN];
+        loop {
+            let len = match tokio::time::timeout_at(deadline, socket.recv(&mut buffer)).await {
+                Ok(Ok(len)) => len,
+                // E.g. an ICMP port unreachable, nothing listens on the server
+                Ok(Err(e)) => return Err(failed(e)),
+                Err(_) => return Err(DnsError::Timeout),
+            };
+            let reply = &buffer[..len];
+            // Late answers to an earlier attempt have another ID
+            if reply.len() < 12 || reply[..2] != id.to_be_bytes() {
+                continue;
             }
-            return PortScanResult::new(ip.to_string().parse().unwrap(), Status::Up, hostname, open_tcp_ports);
-        } else {
-            if verboose {
-                println!("Ping failed");
+            // Truncated, the complete answer only fits into a TCP message
+            if reply[2] & 0x02 != 0 {
+                let reply = tokio::time::timeout_at(deadline, query_dns_tcp(server, id, &query)).await
+                    .map_err(|_| DnsError::Timeout)?
+                    .map_err(failed)?;
+                return parse_dns_reply(&reply, record_type);
             }
-            return PortScanResult::new(ip.to_string().parse().unwrap(), Status::Down, hostname, open_tcp_ports);
+            return parse_dns_reply(reply, record_type);
         }
     }
-*/
+}
+
+// Over TCP every message is prefixed with its length. The answer has to carry the ID of the query like over UDP
+async fn query_dns_tcp(server: SocketAddr, id: u16, query: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(server).await?;
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend(query);
+    stream.write_all(&message).await?;
+
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let mut reply = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut reply).await?;
+    if reply.get(..2) != Some(&id.to_be_bytes()[..]) {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "answer to another query"));
+    }
+    Ok(reply)
+}
+
+// e.g. "4.3.2.1.in-addr.arpa" for 1.2.3.4, IPv6 addresses nibble by nibble under ip6.arpa
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        },
+        IpAddr::V6(ip) => {
+            let mut name = String::with_capacity(72);
+            for byte in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        },
+    }
+}
 
-pub fn analyse_interfaces() -> () {
-    // All interfaces
-    let interfaces = datalink::interfaces();
+// Standard query with recursion desired. None if the name is not a valid DNS name
+fn encode_dns_query(id: u16, name: &str, record_type: RecordType) -> Option<Vec<u8>> {
+    let mut query = id.to_be_bytes().to_vec();
+    query.extend([0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    let name = name.trim_end_matches('.');
+    if name.is_empty() || name.len() > 253 {
+        return None;
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(record_type.code().to_be_bytes());
+    // Class IN
+    query.extend([0x00, 0x01]);
+    Some(query)
+}
 
-    // Filter out loopback interfaces and interfaces that are not up
-    let interfaces_no_loopback: Vec<NetworkInterface> = interfaces.into_iter()
-        .filter(|iface| !iface.is_loopback())
-        // .filter(|iface| iface.is_up()) // This excludes every interface on Windows, works on Macos though
-        .filter(|iface| iface.ips.len() > 0)
-        .collect();
+// The answer records of the asked for type. Aliases are resolved by the server, their CNAME records are skipped
+fn parse_dns_reply(reply: &[u8], record_type: RecordType) -> Result<Vec<Record>, DnsError> {
+    let malformed = || DnsError::Failed(String::from("malformed answer"));
+    let header = reply.get(..12).ok_or_else(malformed)?;
+    if header[2] & 0x80 == 0 {
+        return Err(malformed());
+    }
+    match header[3] & 0x0f {
+        0 => {},
+        2 => return Err(DnsError::Failed(String::from("server failure"))),
+        3 => return Err(DnsError::NotFound),
+        5 => return Err(DnsError::Failed(String::from("query refused"))),
+        code => return Err(DnsError::Failed(format!("error code {}", code))),
+    }
+    let n_questions = u16::from_be_bytes([header[4], header[5]]);
+    let n_answers = u16::from_be_bytes([header[6], header[7]]);
 
-    for interface in interfaces_no_loopback {
+    let mut offset = 12;
+    for _ in 0..n_questions {
+        offset = skip_dns_name(reply, offset).ok_or_else(malformed)? + 4;
+    }
+    let mut records = Vec::new();
+    for _ in 0..n_answers {
+        offset = skip_dns_name(reply, offset).ok_or_else(malformed)?;
+        let fields = reply.get(offset..offset + 10).ok_or_else(malformed)?;
+        let code = u16::from_be_bytes([fields[0], fields[1]]);
+        let len = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+        let start = offset + 10;
+        let data = reply.get(start..start + len).ok_or_else(malformed)?;
+        offset = start + len;
 
-        // Show the interface description
-        let interface_text = if !interface.description.is_empty() {
-            format!("Interface: {} - {}", interface.name, interface.description)
-        } else {
-            format!("Interface: {}", interface.name)
+        if code != record_type.code() {
+            continue;
+        }
+        let record = match record_type {
+            RecordType::A => Record::A(Ipv4Addr::from(<[u8; 4]>::try_from(data).map_err(|_| malformed())?)),
+            RecordType::Aaaa => Record::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(data).map_err(|_| malformed())?)),
+            RecordType::Ptr => Record::Ptr(read_dns_name(reply, start).ok_or_else(malformed)?),
+            // One or more strings, each prefixed with its length
+            RecordType::Txt => {
+                let mut text = Vec::new();
+                let mut rest = data;
+                while let Some((len, tail)) = rest.split_first() {
+                    let len = (*len as usize).min(tail.len());
+                    text.extend(&tail[..len]);
+                    rest = &tail[len..];
+                }
+                Record::Txt(String::from_utf8_lossy(&text).into_owned())
+            },
         };
+        records.push(record);
+    }
+    if records.is_empty() {
+        return Err(DnsError::NotFound);
+    }
+    Ok(records)
+}
 
-        println!("{}", interface_text);
+// Offset behind the name at `offset`, a compressed name ends with its pointer
+fn skip_dns_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(offset)? as usize;
+        match len {
+            0 => return Some(offset + 1),
+            _ if len & 0xc0 == 0xc0 => return Some(offset + 2),
+            _ => offset += 1 + len,
+        }
+    }
+}
 
-        // Print the IPs of possibly relevant interfaces
-        for ipv4network in interface.ips {
-            if ipv4network.ip().is_ipv4() && ipv4network.prefix() > 0 {
-                println!("-- Possible interesting IPv4 Address: {}/{}", ipv4network.ip(), ipv4network.prefix());
+// Name at `offset`, following the compression pointers
+fn read_dns_name(message: &[u8], mut offset: usize) -> Option<String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut jumps = 0;
+    loop {
+        let len = *message.get(offset)? as usize;
+        if len == 0 {
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > MAX_DNS_NAME_JUMPS {
+                return None;
             }
+            offset = ((len & 0x3f) << 8) | *message.get(offset + 1)? as usize;
+            continue;
         }
+        labels.push(String::from_utf8_lossy(message.get(offset + 1..offset + 1 + len)?).into_owned());
+        offset += 1 + len;
     }
+    Some(labels.join("."))
 }
 
-pub async fn scan_ports_tcp(ip: Ipv4Addr, timeout: Duration, ports: &[u16]) -> Vec<u16> {
-    let mut open_ports: Vec<u16> = Vec::new();
+pub async fn ping_host_surge(clients: &PingClients, ip: IpAddr, options: &PingOptions, adaptive: Option<&AdaptiveTimeout>) -> (Status, PingStats) {
+    
+    // ICMP echo for IPv4, ICMPv6 echo for IPv6 targets
+    // Without a socket of the address family the host can't answer
+    let Some(client) = clients.for_ip(ip) else {
+        return (Status::Down, PingStats::from_replies(0, &[], None));
+    };
+    let mut pinger = client.pinger(ip, PingIdentifier(random())).await;
+    pinger.timeout(options.timeout);
 
-    for port in ports {
-        let address = format!("{}:{}", ip, port);
-        match TcpStream::connect_timeout(
-            &address.parse().unwrap(),
-            timeout
-        ) {
-            Ok(_) => {
-                open_ports.push(*port);
+    // Send the echo requests one interval apart, a request that times out delays the next one
+    let mut interval = tokio::time::interval(options.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut rtts: Vec<Duration> = Vec::new();
+    let mut ttl: Option<u8> = None;
+    for seq in 0..options.count {
+        interval.tick().await;
+        if let Ok((packet, duration)) = pinger.ping(PingSequence(seq), &PING_PAYLOAD).await {
+            // surge-ping does not read the hop limit of ICMPv6 replies, only IPv4 has a TTL
+            if let IcmpPacket::V4(packet) = packet {
+                ttl = ttl.or(packet.get_ttl());
+            }
+            if let Some(adaptive) = adaptive {
+                adaptive.observe(duration);
             }
-            Err(_) => {}
+            rtts.push(duration);
         }
     }
 
-    open_ports
+    let status = if rtts.is_empty() { Status::Down } else { Status::Up };
+    (status, PingStats::from_replies(options.count, &rtts, ttl))
 }
 
-pub async fn reverse_dns_lookup(ip: Ipv4Addr) -> String {
-    // do the lookup
-    match lookup_addr(&IpAddr::from(ip)) {
-        Ok(name) => name,
-        Err(_) => String::from("Unknown"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+    // A port nothing listens on: bind it to get a free one, then close it again
+    async fn closed_port() -> u16 {
+        TcpListener::bind((LOCALHOST, 0)).await.unwrap().local_addr().unwrap().port()
     }
-}
 
-pub async fn ping_host_surge(client: &Arc<Client>, ip: Ipv4Addr, verboose: bool) -> Status {
-    
-    let mut pinger = client.pinger(IpAddr::V4(ip), PingIdentifier(random())).await;
-    let ping_result = pinger.ping(PingSequence(0), &PING_PAYLOAD).await;
-    match ping_result {
-        Ok((_packet, _duration)) => {
-            if verboose {
-                println!("Ping successful");
+    #[tokio::test]
+    async fn scan_ports_tcp_tells_open_from_closed() {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = closed_port().await;
+        let permits = Semaphore::new(16);
+
+        let states = scan_ports_tcp(LOCALHOST, TEST_TIMEOUT, &[open, closed], &permits, None).await;
+        let mut expected = vec![(open, PortState::Open), (closed, PortState::Closed)];
+        expected.sort_unstable_by_key(|(port, _)| *port);
+        assert_eq!(states, expected);
+    }
+
+    #[tokio::test]
+    async fn scan_ports_tcp_reports_every_port_sorted_with_few_permits() {
+        let listeners: Vec<TcpListener> = stream::iter(0..20)
+            .then(|_| async { TcpListener::bind((LOCALHOST, 0)).await.unwrap() })
+            .collect()
+            .await;
+        let mut ports: Vec<u16> = listeners.iter().map(|listener| listener.local_addr().unwrap().port()).collect();
+        ports.reverse();
+        let permits = Semaphore::new(2);
+
+        let states = scan_ports_tcp(LOCALHOST, TEST_TIMEOUT, &ports, &permits, None).await;
+        ports.sort_unstable();
+        assert_eq!(states.iter().map(|(port, _)| *port).collect::<Vec<u16>>(), ports);
+        assert!(states.iter().all(|(_, state)| *state == PortState::Open));
+        assert_eq!(permits.available_permits(), 2);
+    }
+
+    fn ms(ms: &[u64]) -> Vec<Duration> {
+        ms.iter().map(|ms| Duration::from_millis(*ms)).collect()
+    }
+
+    #[test]
+    fn ping_stats_summarise_the_replies_like_ping() {
+        let stats = PingStats::from_replies(4, &ms(&[10, 20, 30]), Some(64));
+        assert_eq!((stats.transmitted, stats.received), (4, 3));
+        assert_eq!(stats.packet_loss, 25.0);
+        assert_eq!(stats.rtt_min_ms, Some(10.0));
+        assert_eq!(stats.rtt_avg_ms, Some(20.0));
+        assert_eq!(stats.rtt_max_ms, Some(30.0));
+        // Population standard deviation: sqrt(200 / 3)
+        assert_eq!(stats.rtt_stddev_ms, Some(8.165));
+        assert_eq!(stats.ttl, Some(64));
+    }
+
+    #[test]
+    fn ping_stats_without_replies_have_no_round_trip_times() {
+        let stats = PingStats::from_replies(3, &[], None);
+        assert_eq!(stats.packet_loss, 100.0);
+        assert_eq!((stats.rtt_min_ms, stats.rtt_avg_ms, stats.rtt_max_ms, stats.rtt_stddev_ms), (None, None, None, None));
+        // Nothing sent, nothing lost
+        assert_eq!(PingStats::from_replies(0, &[], None).packet_loss, 0.0);
+    }
+
+    #[test]
+    fn rtt_estimate_follows_rfc_6298() {
+        let mut estimate = RttEstimate::new(Duration::from_millis(100));
+        // srtt 100 + 4 * rttvar 50
+        assert_eq!(estimate.timeout(), Duration::from_millis(300));
+        estimate.update(Duration::from_millis(100));
+        // rttvar shrinks to 37.5 while srtt stays
+        assert_eq!(estimate.timeout(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn rtt_estimate_timeout_is_clamped() {
+        assert_eq!(RttEstimate::new(Duration::from_micros(100)).timeout(), MIN_ADAPTIVE_TIMEOUT);
+        assert_eq!(RttEstimate::new(Duration::from_secs(5)).timeout(), MAX_ADAPTIVE_TIMEOUT);
+    }
+
+    #[test]
+    fn rtt_estimate_from_ping_uses_the_larger_variation() {
+        let stats = PingStats::from_replies(3, &ms(&[40, 100, 160]), None);
+        let estimate = RttEstimate::from_ping(&stats).unwrap();
+        // The standard deviation of 48.99 ms is below half the average, so rttvar is 50
+        assert_eq!(estimate.timeout(), Duration::from_millis(300));
+        assert_eq!(RttEstimate::from_ping(&PingStats::from_replies(1, &[], None)), None);
+    }
+
+    #[test]
+    fn adaptive_timeout_starts_empty_and_learns() {
+        let adaptive = AdaptiveTimeout::new();
+        assert_eq!(adaptive.estimate(), None);
+        adaptive.observe(Duration::from_millis(20));
+        adaptive.observe(Duration::from_millis(20));
+        assert!(adaptive.estimate().unwrap().timeout() < Duration::from_millis(80));
+    }
+
+    #[tokio::test]
+    async fn ping_clients_only_open_the_requested_families() {
+        let clients = PingClients::new(true, false).unwrap();
+        assert_eq!(clients.method_for(IpAddr::V6(Ipv6Addr::LOCALHOST)), None);
+
+        // A raw socket with root or CAP_NET_RAW, a datagram socket where net.ipv4.ping_group_range allows it, else nothing
+        let options = PingOptions { count: 2, interval: Duration::from_millis(10), timeout: TEST_TIMEOUT };
+        let (status, stats) = ping_host_surge(&clients, LOCALHOST, &options, None).await;
+        match clients.method_for(LOCALHOST) {
+            Some(_) => {
+                assert_eq!(status, Status::Up);
+                assert_eq!((stats.transmitted, stats.received), (2, 2));
+            },
+            None => assert_eq!(status, Status::Down),
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_without_a_client_reports_the_host_down() {
+        let clients = PingClients::new(false, false).unwrap();
+        assert_eq!(clients.method_for(LOCALHOST), None);
+        let (status, stats) = ping_host_surge(&clients, LOCALHOST, &PingOptions::default(), None).await;
+        assert_eq!(status, Status::Down);
+        assert_eq!(stats.transmitted, 0);
+    }
+
+    #[tokio::test]
+    async fn ping_host_tcp_counts_accepted_and_refused_connections() {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = closed_port().await;
+        let permits = Semaphore::new(16);
+
+        assert_eq!(ping_host_tcp(LOCALHOST, TEST_TIMEOUT, &[open], &permits, None).await, Some(Reason::SynAck(open)));
+        assert_eq!(ping_host_tcp(LOCALHOST, TEST_TIMEOUT, &[closed], &permits, None).await, Some(Reason::ConnRefused(closed)));
+    }
+
+    #[tokio::test]
+    async fn ping_host_tcp_without_ports_gets_no_answer() {
+        let permits = Semaphore::new(16);
+        assert_eq!(ping_host_tcp(LOCALHOST, TEST_TIMEOUT, &[], &permits, None).await, None);
+    }
+
+    #[test]
+    fn probe_errors_follow_the_io_error() {
+        assert_eq!(ProbeError::from(ErrorKind::HostUnreachable), ProbeError::HostUnreachable);
+        assert_eq!(ProbeError::from(ErrorKind::NetworkUnreachable), ProbeError::NetworkUnreachable);
+        assert_eq!(ProbeError::from(ErrorKind::PermissionDenied), ProbeError::PermissionDenied);
+        assert_eq!(ProbeError::from(ErrorKind::AddrNotAvailable), ProbeError::AddressNotAvailable);
+        assert_eq!(ProbeError::from(ErrorKind::TimedOut), ProbeError::Other);
+        assert_eq!(ProbeError::HostUnreachable.name(), "host-unreach");
+        assert_eq!(PortState::Error(ProbeError::PermissionDenied).name(), "error");
+        assert_eq!(PortState::OpenFiltered.name(), "open|filtered");
+    }
+
+    #[tokio::test]
+    async fn dns_cache_remembers_answers() {
+        let cache = DnsCache::new();
+        let first = cache.reverse_lookup(LOCALHOST, TEST_TIMEOUT, None).await;
+        assert_ne!(first, Err(DnsError::Timeout));
+        assert_eq!(cache.entries.lock().unwrap().get(&LOCALHOST), Some(&first));
+        assert_eq!(cache.reverse_lookup(LOCALHOST, TEST_TIMEOUT, None).await, first);
+    }
+
+    #[tokio::test]
+    async fn dns_cache_waits_for_a_resolver_thread_and_forgets_timeouts() {
+        // Every resolver thread is taken, e.g. by lookups a hanging resolver never answered
+        let cache = DnsCache { entries: Mutex::new(HashMap::new()), resolver_permits: Arc::new(Semaphore::new(0)) };
+        let lookup = cache.reverse_lookup(LOCALHOST, Duration::from_millis(50), None).await;
+        assert_eq!(lookup, Err(DnsError::Timeout));
+        assert!(cache.entries.lock().unwrap().is_empty());
+
+        cache.resolver_permits.add_permits(1);
+        assert_ne!(cache.reverse_lookup(LOCALHOST, TEST_TIMEOUT, None).await, Err(DnsError::Timeout));
+        assert_eq!(cache.resolver_permits.available_permits(), 1);
+    }
+
+    // Answers a query from the stand-in zone, over UDP or TCP
+    type DnsHandler = fn(&[u8], bool) -> Option<Vec<u8>>;
+
+    // Stand-in DNS server on UDP and TCP of the same port
+    async fn dns_server(handler: DnsHandler) -> SocketAddr {
+        let udp = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let address = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(address).await.unwrap();
+        tokio::spawn(async move {
+            let mut query = [0u8; 512];
+            while let Ok((len, peer)) = udp.recv_from(&mut query).await {
+                if let Some(reply) = handler(&query[..len], false) {
+                    let _ = udp.send_to(&reply, peer).await;
+                }
             }
-            Status::Up
-        },
-        Err(_) => {
-            if verboose {
-                println!("Ping not successful");
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = tcp.accept().await {
+                let mut len = [0u8; 2];
+                if stream.read_exact(&mut len).await.is_err() {
+                    continue;
+                }
+                let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+                if stream.read_exact(&mut query).await.is_err() {
+                    continue;
+                }
+                if let Some(reply) = handler(&query, true) {
+                    let mut message = (reply.len() as u16).to_be_bytes().to_vec();
+                    message.extend(reply);
+                    let _ = stream.write_all(&message).await;
+                }
             }
-            Status::Down
+        });
+        address
+    }
+
+    fn question_type(query: &[u8]) -> u16 {
+        let offset = skip_dns_name(query, 12).unwrap();
+        u16::from_be_bytes([query[offset], query[offset + 1]])
+    }
+
+    // Name in wire format, without compression
+    fn dns_name(name: &str) -> Vec<u8> {
+        let mut encoded: Vec<u8> = name.split('.').flat_map(|label| [&[label.len() as u8], label.as_bytes()].concat()).collect();
+        encoded.push(0);
+        encoded
+    }
+
+    // Reply with the question of the query and the records, whose owner points to the question name
+    fn answer(query: &[u8], rcode: u8, truncated: bool, records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let question_end = skip_dns_name(query, 12).unwrap() + 4;
+        let mut reply = query[..2].to_vec();
+        reply.push(if truncated { 0x83 } else { 0x81 });
+        reply.push(0x80 | rcode);
+        reply.extend([0, 1]);
+        reply.extend((records.len() as u16).to_be_bytes());
+        reply.extend([0, 0, 0, 0]);
+        reply.extend(&query[12..question_end]);
+        for (code, data) in records {
+            reply.extend([0xc0, 0x0c]);
+            reply.extend(code.to_be_bytes());
+            reply.extend([0x00, 0x01, 0x00, 0x00, 0x0e, 0x10]);
+            reply.extend((data.len() as u16).to_be_bytes());
+            reply.extend(data);
+        }
+        reply
+    }
+
+    fn zone(query: &[u8], _: bool) -> Option<Vec<u8>> {
+        let name = read_dns_name(query, 12)?;
+        let records: Vec<(u16, Vec<u8>)> = match (name.as_str(), question_type(query)) {
+            ("10.2.0.192.in-addr.arpa", 12) => vec![(12, dns_name("nas.lan"))],
+            ("0.1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa", 12) => vec![(12, vec![0xc0, 0x0c])],
+            // The server resolved the alias itself, the CNAME comes first
+            ("nas.lan", 1) => vec![(5, dns_name("storage.lan")), (1, vec![192, 0, 2, 10]), (1, vec![192, 0, 2, 11])],
+            ("nas.lan", 28) => vec![(28, "fd00::10".parse::<Ipv6Addr>().unwrap().octets().to_vec())],
+            ("nas.lan", 16) => vec![(16, b"\x05hello\x06 world".to_vec()), (16, b"\x03v=1".to_vec())],
+            ("printer.lan", 1) => vec![(1, vec![192, 0, 2, 20])],
+            ("printer.lan", _) => Vec::new(),
+            ("broken.lan", _) => return Some(answer(query, 2, false, &[])),
+            _ => return Some(answer(query, 3, false, &[])),
+        };
+        Some(answer(query, 0, false, &records))
+    }
+
+    fn client(servers: Vec<SocketAddr>) -> DnsClient {
+        DnsClient::new(servers).timeout(Duration::from_millis(200)).retries(1)
+    }
+
+    #[test]
+    fn encode_dns_query_writes_the_question() {
+        let query = encode_dns_query(0x1234, "nas.lan.", RecordType::Aaaa).unwrap();
+        assert_eq!(query[..12], [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(query[12..], [3, b'n', b'a', b's', 3, b'l', b'a', b'n', 0, 0x00, 0x1c, 0x00, 0x01]);
+        assert_eq!(read_dns_name(&query, 12).as_deref(), Some("nas.lan"));
+
+        assert_eq!(encode_dns_query(1, "", RecordType::A), None);
+        assert_eq!(encode_dns_query(1, "a..lan", RecordType::A), None);
+        assert_eq!(encode_dns_query(1, &format!("{}.lan", "a".repeat(64)), RecordType::A), None);
+        assert_eq!(encode_dns_query(1, &vec!["a".repeat(63); 4].join("."), RecordType::A), None);
+    }
+
+    #[test]
+    fn reverse_name_of_both_families() {
+        assert_eq!(reverse_name("192.0.2.10".parse().unwrap()), "10.2.0.192.in-addr.arpa");
+        assert_eq!(reverse_name("2001:db8::567:89ab".parse().unwrap()), "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa");
+    }
+
+    #[test]
+    fn read_dns_name_stops_pointer_loops() {
+        let message = [0u8, 0, 3, b'l', b'a', b'n', 0xc0, 2];
+        assert_eq!(read_dns_name(&message, 2), None);
+        assert_eq!(read_dns_name(&[3, b'l', b'a'], 0), None);
+        assert_eq!(skip_dns_name(&message, 2), Some(8));
+    }
+
+    #[test]
+    fn parse_dns_reply_rejects_broken_answers() {
+        let query = encode_dns_query(7, "nas.lan", RecordType::A).unwrap();
+        let malformed = Err(DnsError::Failed(String::from("malformed answer")));
+        // A query is no answer
+        assert_eq!(parse_dns_reply(&query, RecordType::A), malformed);
+        let reply = answer(&query, 0, false, &[(1, vec![192, 0, 2, 10])]);
+        assert_eq!(parse_dns_reply(&reply[..reply.len() - 1], RecordType::A), malformed);
+        assert_eq!(parse_dns_reply(&answer(&query, 0, false, &[(1, vec![192, 0, 2])]), RecordType::A), malformed);
+        assert_eq!(parse_dns_reply(&answer(&query, 5, false, &[]), RecordType::A), Err(DnsError::Failed(String::from("query refused"))));
+        assert_eq!(parse_dns_reply(&reply, RecordType::A), Ok(vec![Record::A(Ipv4Addr::new(192, 0, 2, 10))]));
+    }
+
+    #[tokio::test]
+    async fn dns_client_reverse_lookup() {
+        let client = client(vec![dns_server(zone).await]);
+        assert_eq!(client.reverse_lookup("192.0.2.10".parse().unwrap()).await, Ok(String::from("nas.lan")));
+        // The PTR data may point to a name elsewhere in the message
+        assert_eq!(client.reverse_lookup("fd00::10".parse().unwrap()).await.unwrap(), "0.1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa");
+        assert_eq!(client.reverse_lookup("192.0.2.99".parse().unwrap()).await, Err(DnsError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn dns_client_lookup_host_and_txt() {
+        let client = client(vec![dns_server(zone).await]);
+        assert_eq!(client.lookup_host("nas.lan").await.unwrap(), ["192.0.2.10", "192.0.2.11", "fd00::10"].map(|ip| ip.parse::<IpAddr>().unwrap()));
+        // No IPv6 address is no error as long as there is an IPv4 one
+        assert_eq!(client.lookup_host("printer.lan").await.unwrap(), ["192.0.2.20".parse::<IpAddr>().unwrap()]);
+        assert_eq!(client.lookup_host("missing.lan").await, Err(DnsError::NotFound));
+        assert_eq!(client.lookup_txt("nas.lan").await.unwrap(), ["hello world", "v=1"]);
+        assert_eq!(client.lookup_txt("printer.lan").await, Err(DnsError::NotFound));
+    }
+
+    #[tokio::test]
+    async fn dns_client_repeats_truncated_answers_over_tcp() {
+        fn truncating(query: &[u8], tcp: bool) -> Option<Vec<u8>> {
+            Some(match tcp {
+                false => answer(query, 0, true, &[]),
+                true => answer(query, 0, false, &[(1, vec![192, 0, 2, 30])]),
+            })
         }
+        let client = client(vec![dns_server(truncating).await]);
+        assert_eq!(client.query("big.lan", RecordType::A).await, Ok(vec![Record::A(Ipv4Addr::new(192, 0, 2, 30))]));
+    }
+
+    #[tokio::test]
+    async fn dns_client_ignores_tcp_answers_to_another_query() {
+        fn spoofing(query: &[u8], tcp: bool) -> Option<Vec<u8>> {
+            let mut reply = answer(query, 0, !tcp, &[(1, vec![192, 0, 2, 66])]);
+            if tcp {
+                reply[0] ^= 0xff;
+            }
+            Some(reply)
+        }
+        let client = client(vec![dns_server(spoofing).await]);
+        assert_eq!(client.query("big.lan", RecordType::A).await, Err(DnsError::Failed(String::from("answer to another query"))));
+    }
+
+    #[tokio::test]
+    async fn dns_cache_gives_the_client_all_its_retries() {
+        let silent = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let good = dns_server(zone).await;
+        let client = client(vec![silent.local_addr().unwrap(), good]);
+        // Far below the 400ms the client spends on the silent server before it asks the good one
+        let lookup = DnsCache::new().reverse_lookup("192.0.2.10".parse().unwrap(), Duration::from_millis(10), Some(&client)).await;
+        assert_eq!(lookup, Ok(String::from("nas.lan")));
+    }
+
+    #[tokio::test]
+    async fn dns_client_moves_on_from_silent_and_failing_servers() {
+        let silent = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let good = dns_server(zone).await;
+        let client = client(vec![silent.local_addr().unwrap(), good]);
+        assert_eq!(client.reverse_lookup("192.0.2.10".parse().unwrap()).await, Ok(String::from("nas.lan")));
+
+        let failing = client.query("broken.lan", RecordType::A).await;
+        assert_eq!(failing, Err(DnsError::Failed(String::from("server failure"))));
+
+        let client = DnsClient::new(vec![silent.local_addr().unwrap()]).timeout(Duration::from_millis(50)).retries(0);
+        assert_eq!(client.query("nas.lan", RecordType::A).await, Err(DnsError::Timeout));
+        assert_eq!(DnsClient::new(Vec::new()).query("nas.lan", RecordType::A).await, Err(DnsError::Failed(String::from("no DNS server"))));
     }
 }
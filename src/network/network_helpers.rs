@@ -1,46 +1,244 @@
-#![allow(unused_imports)]
-#[allow(dead_code)]
-
-use std::net::Ipv4Addr;
-
-pub fn create_ip_from_range(range_ip: (String, String)) -> Vec<String> {
-    let mut ip_list = vec![];
-    
-    let start: Ipv4Addr = range_ip.0.parse().unwrap();
-    let end: Ipv4Addr = range_ip.1.parse().unwrap();
-    let mut current_ip = start;
-    
-    while current_ip <= end {
-        ip_list.push(current_ip.to_string());
-        current_ip = Ipv4Addr::from(u32::from(current_ip) + 1);
-    }
-    
-    ip_list
-}
-
-pub fn split_ip_range(start_ip: Ipv4Addr, end_ip: Ipv4Addr, chunksize: usize) -> (Vec<(String, String)>, u32) {
-    let mut ranges = vec![];
-    let total_ips = (u32::from(end_ip) - u32::from(start_ip)) + 1;
-
-    if chunksize > total_ips as usize {
-        // If the chunk size is larger than the total number of IPs, just return the whole range
-        ranges.push((start_ip.to_string(), end_ip.to_string()));
-        return (ranges, total_ips);
-    }
-    
-    let chunk_size = (total_ips / chunksize as u32) as usize;
-    
-    let mut current_ip = start_ip;
-    
-    while current_ip < end_ip {
-        if u32::from(end_ip) - u32::from(current_ip) < chunk_size as u32 {
-            ranges.push((current_ip.to_string(), end_ip.to_string()));
-            break;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::collections::BTreeSet;
+
+use crate::error::ScanError;
+use crate::network::network_core::{DNS_PORT, TOP_100_TCP_PORTS};
+
+// IPv6 networks are huge, only networks and ranges of at most 256 addresses can be scanned
+pub const MIN_IPV6_PREFIX: u8 = 120;
+
+pub fn ip_to_u128(ip: IpAddr) -> u128 {
+    // Common integer representation of IPv4 and IPv6 addresses for range arithmetic
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+pub fn ip_from_u128(value: u128, ipv6: bool) -> IpAddr {
+    if ipv6 {
+        IpAddr::V6(Ipv6Addr::from(value))
+    } else {
+        IpAddr::V4(Ipv4Addr::from(value as u32))
+    }
+}
+
+pub fn validate_range(start: IpAddr, end: IpAddr) -> Result<(), ScanError> {
+    if start.is_ipv4() != end.is_ipv4() {
+        return Err(ScanError::InvalidTarget {
+            target: format!("{}-{}", start, end),
+            reason: String::from("both ends of a range must be of the same address family"),
+        });
+    }
+    if start > end {
+        return Err(ScanError::InvertedRange { start, end });
+    }
+    if end.is_ipv6() && ip_to_u128(end) - ip_to_u128(start) >= 1 << (128 - MIN_IPV6_PREFIX) {
+        return Err(ScanError::InvalidTarget {
+            target: format!("{}-{}", start, end),
+            reason: format!("IPv6 targets can span at most {} addresses, i.e. a /{}", 1 << (128 - MIN_IPV6_PREFIX), MIN_IPV6_PREFIX),
+        });
+    }
+    Ok(())
+}
+
+pub fn parse_dns_server(spec: &str) -> Result<SocketAddr, ScanError> {
+    // An address with a port, e.g. for a stand-in server, or just an address for the standard port
+    let spec = spec.trim();
+    if let Ok(address) = spec.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    match spec.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, DNS_PORT)),
+        Err(_) => Err(ScanError::InvalidDnsServer { server: spec.to_string() }),
+    }
+}
+
+pub fn parse_port_spec(spec: &str) -> Result<Vec<u16>, ScanError> {
+    // nmap style port specification, e.g. "22,80,8000-8100", "-" for all ports or "top-100"
+    // A BTreeSet removes duplicates of overlapping entries and keeps the ports sorted
+    let mut ports: BTreeSet<u16> = BTreeSet::new();
+    let invalid = |reason: String| ScanError::InvalidPorts { spec: spec.to_string(), reason };
+
+    for part in spec.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            return Err(invalid(String::from("empty entry")));
+        }
+
+        if part == "top-100" {
+            ports.extend(TOP_100_TCP_PORTS);
+            continue;
+        }
+
+        match part.split_once('-') {
+            Some((start, end)) => {
+                // Like nmap, a missing start or end of a range means port 1 or 65535, so "-" is every port
+                let start: u16 = if start.is_empty() { 1 } else { parse_port(start).map_err(invalid)? };
+                let end: u16 = if end.is_empty() { u16::MAX } else { parse_port(end).map_err(invalid)? };
+                if start > end {
+                    return Err(invalid(format!("range '{}' is inverted, make sure the start is not greater than the end", part)));
+                }
+                ports.extend(start..=end);
+            },
+            None => {
+                ports.insert(parse_port(part).map_err(invalid)?);
+            }
         }
-        let next_ip = Ipv4Addr::from(u32::from(current_ip) + chunk_size as u32);
-        ranges.push((current_ip.to_string(), next_ip.to_string()));
-        current_ip = Ipv4Addr::from(u32::from(next_ip) + 1);
     }
-    
-    (ranges, total_ips)
-}
\ No newline at end of file
+
+    Ok(ports.into_iter().collect())
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port.trim().parse::<u16>() {
+        Ok(0) => Err(String::from("port 0 can not be scanned, valid ports are 1-65535")),
+        Ok(port) => Ok(port),
+        Err(_) => Err(format!("failed to parse '{}' as a port, valid ports are 1-65535", port)),
+    }
+}
+
+pub fn format_port_spec(ports: &[u16]) -> String {
+    // Inverse of parse_port_spec, collapses consecutive ports into ranges, e.g. [20,21,22,80] -> "20-22,80"
+    let mut parts: Vec<String> = Vec::new();
+    let mut i = 0;
+
+    while i < ports.len() {
+        let start = ports[i];
+        let mut end = start;
+        while i + 1 < ports.len() && ports[i + 1] as u32 == end as u32 + 1 {
+            i += 1;
+            end = ports[i];
+        }
+        if start == end {
+            parts.push(start.to_string());
+        } else {
+            parts.push(format!("{}-{}", start, end));
+        }
+        i += 1;
+    }
+
+    parts.join(",")
+}
+
+pub fn well_known_service(port: u16) -> Option<&'static str> {
+    // Service names as listed in nmap-services for the most common TCP ports
+    let service = match port {
+        7 => "echo",
+        20 => "ftp-data",
+        21 => "ftp",
+        22 => "ssh",
+        23 => "telnet",
+        25 => "smtp",
+        53 => "domain",
+        80 => "http",
+        88 => "kerberos-sec",
+        110 => "pop3",
+        111 => "rpcbind",
+        135 => "msrpc",
+        139 => "netbios-ssn",
+        143 => "imap",
+        389 => "ldap",
+        443 => "https",
+        445 => "microsoft-ds",
+        465 => "smtps",
+        548 => "afp",
+        554 => "rtsp",
+        587 => "submission",
+        631 => "ipp",
+        636 => "ldapssl",
+        873 => "rsync",
+        993 => "imaps",
+        995 => "pop3s",
+        1433 => "ms-sql-s",
+        1521 => "oracle",
+        1723 => "pptp",
+        2049 => "nfs",
+        3306 => "mysql",
+        3389 => "ms-wbt-server",
+        5060 => "sip",
+        5432 => "postgresql",
+        5900 => "vnc",
+        6379 => "redis",
+        8000 => "http-alt",
+        8080 => "http-proxy",
+        8443 => "https-alt",
+        9100 => "jetdirect",
+        27017 => "mongod",
+        _ => return None,
+    };
+    Some(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_port_spec_accepts_lists_and_ranges() {
+        assert_eq!(parse_port_spec("22,80,8000-8002").unwrap(), vec![22, 80, 8000, 8001, 8002]);
+        assert_eq!(parse_port_spec(" 443 , 22 ").unwrap(), vec![22, 443]);
+    }
+
+    #[test]
+    fn parse_port_spec_merges_overlapping_entries() {
+        assert_eq!(parse_port_spec("20-25,22,24-26").unwrap(), (20..=26).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn parse_port_spec_fills_in_open_range_ends() {
+        assert_eq!(parse_port_spec("-").unwrap().len(), 65535);
+        assert_eq!(parse_port_spec("-3").unwrap(), vec![1, 2, 3]);
+        assert_eq!(parse_port_spec("65534-").unwrap(), vec![65534, 65535]);
+    }
+
+    #[test]
+    fn parse_port_spec_expands_top_100() {
+        let ports = parse_port_spec("top-100,1").unwrap();
+        assert_eq!(ports.len(), 101);
+        assert!(ports.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn parse_port_spec_rejects_invalid_entries() {
+        for spec in ["", "22,,80", "0", "65536", "http", "100-20", "1-2-3"] {
+            assert!(matches!(parse_port_spec(spec), Err(ScanError::InvalidPorts { .. })), "{:?} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn format_port_spec_collapses_consecutive_ports() {
+        assert_eq!(format_port_spec(&[20, 21, 22, 80, 443, 444]), "20-22,80,443-444");
+        assert_eq!(format_port_spec(&[65535]), "65535");
+        assert_eq!(format_port_spec(&[]), "");
+    }
+
+    #[test]
+    fn format_port_spec_is_the_inverse_of_parse_port_spec() {
+        let spec = "1-5,7,9-10,65535";
+        assert_eq!(format_port_spec(&parse_port_spec(spec).unwrap()), spec);
+    }
+
+    #[test]
+    fn validate_range_limits_ipv6_ranges() {
+        let start: IpAddr = "fd00::".parse().unwrap();
+        assert!(validate_range(start, "fd00::ff".parse().unwrap()).is_ok());
+        assert!(matches!(validate_range(start, "fd00::100".parse().unwrap()), Err(ScanError::InvalidTarget { .. })));
+        // IPv4 ranges of any size are fine
+        assert!(validate_range("0.0.0.0".parse().unwrap(), "255.255.255.255".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn validate_range_rejects_mixed_families_and_inverted_ranges() {
+        assert!(matches!(validate_range("10.0.0.1".parse().unwrap(), "fd00::1".parse().unwrap()), Err(ScanError::InvalidTarget { .. })));
+        assert!(matches!(validate_range("fd00::2".parse().unwrap(), "fd00::1".parse().unwrap()), Err(ScanError::InvertedRange { .. })));
+    }
+
+    #[test]
+    fn ip_u128_conversion_round_trips() {
+        for ip in ["192.168.0.1", "0.0.0.0", "255.255.255.255", "::", "fd00::1", "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"] {
+            let ip: IpAddr = ip.parse().unwrap();
+            assert_eq!(ip_from_u128(ip_to_u128(ip), ip.is_ipv6()), ip);
+        }
+    }
+}
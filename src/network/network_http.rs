@@ -0,0 +1,495 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+
+use crate::network::network_tls::connect_tls;
+
+// Ports where plain HTTP is the usual protocol
+pub const HTTP_PORTS: [u16; 12] = [80, 81, 591, 3000, 5000, 8000, 8008, 8080, 8081, 8088, 8888, 9000];
+// Ports where HTTPS is the usual protocol
+pub const HTTPS_PORTS: [u16; 4] = [443, 4443, 8443, 9443];
+// The title is usually in the first kilobytes, favicons are rarely larger than this
+const MAX_RESPONSE_LEN: usize = 256 * 1024;
+// Upper bound of simultaneously fetched ports per host
+const MAX_FETCHES_IN_FLIGHT: usize = 8;
+const USER_AGENT: &str = concat!("network_scanner/", env!("CARGO_PKG_VERSION"));
+const MAX_TITLE_LEN: usize = 256;
+
+// Fingerprint of a web server from its answer to `GET /`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HttpInfo {
+    pub status_code: u16,
+    // Value of the Server header, e.g. "nginx/1.18.0 (Ubuntu)"
+    pub server: Option<String>,
+    // Content of the <title> element, whitespace collapsed
+    pub title: Option<String>,
+    // Location header of a redirect, not followed
+    pub location: Option<String>,
+    // Hash of /favicon.ico as used by Shodan (http.favicon.hash): MurmurHash3 of the base64 encoded icon
+    pub favicon_hash: Option<i32>,
+}
+
+// Whether a port is fetched over plain HTTP or over TLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    fn default_port(&self) -> u16 {
+        match self {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
+        }
+    }
+}
+
+// A parsed HTTP response with the body already de-chunked
+struct Response {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+// Fingerprint the web servers on the given ports, ports without an HTTP answer are left out.
+// Every request needs one of the permits shared by all hosts of the scan
+pub async fn fingerprint_http_ports(ip: IpAddr, ports: &[(u16, Scheme)], host: Option<&str>, timeout: Duration, permits: &Semaphore) -> Vec<(u16, HttpInfo)> {
+    let mut infos: Vec<(u16, HttpInfo)> = stream::iter(ports.iter().copied())
+        .map(|(port, scheme)| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            fingerprint_http(ip, port, scheme, host, timeout).await.map(|info| (port, info))
+        })
+        .buffer_unordered(MAX_FETCHES_IN_FLIGHT)
+        .filter_map(|info| async move { info })
+        .collect()
+        .await;
+    infos.sort_unstable_by_key(|(port, _)| *port);
+    infos
+}
+
+// Fetch / and /favicon.ico. `host` goes into the Host header and the SNI of HTTPS, the address is used without it.
+// None if the port doesn't answer with HTTP
+pub async fn fingerprint_http(ip: IpAddr, port: u16, scheme: Scheme, host: Option<&str>, timeout: Duration) -> Option<HttpInfo> {
+    let address = SocketAddr::new(ip, port);
+    let page = get(address, scheme, host, "/", timeout).await?;
+
+    let favicon = get(address, scheme, host, "/favicon.ico", timeout).await
+        .filter(|favicon| favicon.status_code == 200 && !favicon.body.is_empty())
+        .map(|favicon| favicon_hash(&favicon.body));
+    let title = page.header("content-type")
+        .is_none_or(|content_type| content_type.to_ascii_lowercase().contains("html"))
+        .then(|| parse_title(&page.body))
+        .flatten();
+
+    Some(HttpInfo {
+        status_code: page.status_code,
+        server: page.header("server").map(printable),
+        title,
+        location: page.header("location").filter(|_| (300..400).contains(&page.status_code)).map(printable),
+        favicon_hash: favicon,
+    })
+}
+
+// Name and port like a browser sends them, the port is left out if it's the default one
+fn host_header(address: SocketAddr, scheme: Scheme, host: Option<&str>) -> String {
+    let host = match (host, address.ip()) {
+        (Some(host), _) => host.to_string(),
+        (None, IpAddr::V4(ipv4)) => ipv4.to_string(),
+        (None, IpAddr::V6(ipv6)) => format!("[{}]", ipv6),
+    };
+    let port = address.port();
+    if port == scheme.default_port() {
+        host
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+async fn get(address: SocketAddr, scheme: Scheme, host: Option<&str>, path: &str, timeout: Duration) -> Option<Response> {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        path,
+        host_header(address, scheme, host),
+        USER_AGENT,
+    );
+    let response = match scheme {
+        Scheme::Http => {
+            let stream = tokio::time::timeout(timeout, TcpStream::connect(address)).await.ok()?.ok()?;
+            exchange(stream, request.as_bytes(), timeout).await?
+        },
+        Scheme::Https => exchange(connect_tls(address, host, timeout).await?, request.as_bytes(), timeout).await?,
+    };
+    parse_response(&response)
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, request: &[u8], timeout: Duration) -> Option<Vec<u8>> {
+    stream.write_all(request).await.ok()?;
+
+    // Connection: close, so the response ends when the server closes. Stop early at the size limit,
+    // or once the body is complete for servers that keep the connection open anyway
+    let mut response: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 8192];
+    while response.len() < MAX_RESPONSE_LEN {
+        match tokio::time::timeout(timeout, stream.read(&mut buffer)).await {
+            Ok(Ok(n)) if n > 0 => response.extend(&buffer[..n]),
+            _ => break,
+        }
+        if is_complete(&response) {
+            break;
+        }
+    }
+    Some(response)
+}
+
+fn split_head(response: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = response.windows(4).position(|window| window == b"\r\n\r\n")?;
+    Some((&response[..end], &response[end + 4..]))
+}
+
+fn is_complete(response: &[u8]) -> bool {
+    let Some((head, body)) = split_head(response) else {
+        return false;
+    };
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    match head.lines().find_map(|line| line.strip_prefix("content-length:")) {
+        Some(len) => len.trim().parse::<usize>().is_ok_and(|len| body.len() >= len),
+        None => head.contains("transfer-encoding: chunked") && body.ends_with(b"0\r\n\r\n"),
+    }
+}
+
+fn parse_response(response: &[u8]) -> Option<Response> {
+    // A response cut off at the size limit still has its headers, the body is just shorter
+    let (head, body) = split_head(response).unwrap_or((response, &[]));
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.lines();
+
+    // "HTTP/1.1 301 Moved Permanently"
+    let mut status_line = lines.next()?.split_whitespace();
+    if !status_line.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let status_code: u16 = status_line.next()?.parse().ok()?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let chunked = headers.iter().any(|(name, value)| name.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked"));
+    let body = if chunked { dechunk(body) } else { body.to_vec() };
+    Some(Response { status_code, headers, body })
+}
+
+// Chunked transfer encoding: hex size, CRLF, data, CRLF, until a chunk of size 0
+fn dechunk(mut body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    while let Some(end) = body.windows(2).position(|window| window == b"\r\n") {
+        let size = String::from_utf8_lossy(&body[..end]);
+        let Ok(size) = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16) else {
+            break;
+        };
+        if size == 0 {
+            break;
+        }
+        let chunk = &body[end + 2..];
+        data.extend(&chunk[..size.min(chunk.len())]);
+        // A size this close to the limit can only come from a broken or hostile server
+        let Some(next) = size.checked_add(2) else {
+            break;
+        };
+        body = chunk.get(next..).unwrap_or_default();
+    }
+    data
+}
+
+fn parse_title(body: &[u8]) -> Option<String> {
+    let html = String::from_utf8_lossy(body);
+    let lower = html.to_ascii_lowercase();
+    let start = lower.find("<title")?;
+    let start = start + lower[start..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+
+    let title = html[start..end].split_whitespace().collect::<Vec<&str>>().join(" ");
+    let title = title.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&#39;", "'").replace("&amp;", "&");
+    let title = printable(&title);
+    let title: String = title.trim().chars().take(MAX_TITLE_LEN).collect();
+    (!title.is_empty()).then_some(title)
+}
+
+// Text from the remote host without control characters, so it can't smuggle terminal escapes into a report
+fn printable(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+// Shodan hashes the favicon base64 encoded like Python's base64.encodebytes, with a newline after every 76 characters
+fn favicon_hash(icon: &[u8]) -> i32 {
+    let encoded = base64(icon);
+    let mut text = Vec::with_capacity(encoded.len() + encoded.len() / 76 + 1);
+    for line in encoded.as_bytes().chunks(76) {
+        text.extend(line);
+        text.push(b'\n');
+    }
+    murmur3_32(&text, 0) as i32
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// 32 bit MurmurHash3 (x86 variant), the one of Python's mmh3.hash
+fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut hash = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in chunks.by_ref() {
+        let k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]).wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash = (hash ^ k).rotate_left(13).wrapping_mul(5).wrapping_add(0xe6546b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k: u32 = 0;
+        for (i, byte) in tail.iter().enumerate() {
+            k |= (*byte as u32) << (8 * i);
+        }
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85ebca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2ae35);
+    hash ^ (hash >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::sync::Arc;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::ServerConfig;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+    const PAGE: &[u8] = b"HTTP/1.1 200 OK\r\nServer: nginx/1.18.0 (Ubuntu)\r\nContent-Type: text/html\r\nTransfer-Encoding: chunked\r\n\r\n\
+        10\r\n<html><head><tit\r\n19;ext=1\r\nle>Router &amp; Modem</ti\r\n12\r\ntle></head></html>\r\n0\r\n\r\n";
+    // Shodan's recipe, mmh3.hash(base64.encodebytes(icon)) in Python, for the bytes 0 to 255
+    const ICON_HASH: i32 = -757223386;
+
+    fn icon() -> Vec<u8> {
+        (0..=255).collect()
+    }
+
+    // Answers / with the page and /favicon.ico with the icon, both with Content-Length, over any stream
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, page: &[u8]) {
+        let mut request = vec![0u8; 4096];
+        let Ok(n) = stream.read(&mut request).await else {
+            return;
+        };
+        let response = if request[..n].starts_with(b"GET /favicon.ico ") {
+            let icon = icon();
+            let mut response = format!("HTTP/1.1 200 OK\r\nContent-Type: image/x-icon\r\nContent-Length: {}\r\n\r\n", icon.len()).into_bytes();
+            response.extend(icon);
+            response
+        } else {
+            page.to_vec()
+        };
+        let _ = stream.write_all(&response).await;
+        let _ = stream.shutdown().await;
+    }
+
+    async fn http_server(page: &'static [u8]) -> u16 {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, page));
+            }
+        });
+        port
+    }
+
+    async fn https_server(page: &'static [u8]) -> u16 {
+        let certificate = CertificateDer::from(include_bytes!("../../data/test/rsa.der").to_vec());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(include_bytes!("../../data/test/rsa.pk8").to_vec()));
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = acceptor.accept(stream).await {
+                        serve(stream, page).await;
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn dechunk_joins_the_chunks() {
+        assert_eq!(dechunk(b"4\r\nWiki\r\n6;name=value\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\n\r\n"), b"Wikipedia in \r\n\r\nchunks.");
+        // Cut off at the size limit
+        assert_eq!(dechunk(b"4\r\nWiki\r\n10\r\nped"), b"Wikiped");
+        assert_eq!(dechunk(b"zz\r\nWiki\r\n"), b"");
+    }
+
+    #[test]
+    fn dechunk_survives_hostile_sizes() {
+        assert_eq!(dechunk(b"4\r\nWiki\r\nffffffffffffffff\r\npedia\r\n0\r\n\r\n"), b"Wikipedia\r\n0\r\n\r\n");
+        assert_eq!(dechunk(b"fffffffffffffffe\r\nabc\r\n4\r\nWiki\r\n"), b"abc\r\n4\r\nWiki\r\n");
+        assert_eq!(dechunk(b"10000000000000000\r\nabc\r\n"), b"");
+    }
+
+    #[test]
+    fn parse_title_cleans_up_the_title() {
+        assert_eq!(parse_title(b"<HTML><TITLE lang=\"en\">\n  Index of\t/ &lt;root&gt;\n</Title>"), Some(String::from("Index of / <root>")));
+        assert_eq!(parse_title(b"<title></title>"), None);
+        assert_eq!(parse_title(b"<title>never closed"), None);
+        assert_eq!(parse_title(b"<html>no title</html>"), None);
+        assert_eq!(parse_title(format!("<title>{}</title>", "x".repeat(1000)).as_bytes()).unwrap().len(), MAX_TITLE_LEN);
+    }
+
+    #[test]
+    fn parse_title_drops_control_characters() {
+        assert_eq!(parse_title(b"<title>\x1b[2J\x1b]0;owned\x07Router\x00</title>"), Some(String::from("[2J]0;ownedRouter")));
+        assert_eq!(parse_title(b"<title>\x1b\x07</title>"), None);
+    }
+
+    #[tokio::test]
+    async fn fingerprint_http_drops_control_characters_from_headers() {
+        let port = http_server(b"HTTP/1.1 301 Moved\r\nServer: evil\x1b[31m/1.0\r\nLocation: /\x1b[0mlogin\r\nContent-Length: 0\r\n\r\n").await;
+        let info = fingerprint_http(LOCALHOST, port, Scheme::Http, None, TEST_TIMEOUT).await.unwrap();
+        assert_eq!(info.server.as_deref(), Some("evil[31m/1.0"));
+        assert_eq!(info.location.as_deref(), Some("/[0mlogin"));
+    }
+
+    #[test]
+    fn parse_response_reads_status_and_headers() {
+        let response = parse_response(b"HTTP/1.0 301 Moved Permanently\r\nlocation: https://example.com/\r\nContent-Length: 0\r\n\r\n").unwrap();
+        assert_eq!(response.status_code, 301);
+        assert_eq!(response.header("Location"), Some("https://example.com/"));
+        assert_eq!(response.header("Server"), None);
+
+        assert_eq!(parse_response(PAGE).unwrap().body, b"<html><head><title>Router &amp; Modem</title></head></html>");
+        assert!(parse_response(b"SSH-2.0-OpenSSH_9.6\r\n").is_none());
+        assert!(parse_response(b"HTTP/1.1 abc\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn is_complete_knows_the_end_of_the_body() {
+        assert!(is_complete(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"));
+        assert!(!is_complete(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nok"));
+        assert!(is_complete(PAGE));
+        assert!(!is_complete(&PAGE[..PAGE.len() - 3]));
+        assert!(!is_complete(b"HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn host_header_leaves_out_default_ports() {
+        let ipv4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 80);
+        let ipv6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 443);
+        assert_eq!(host_header(ipv4, Scheme::Http, None), "192.0.2.1");
+        assert_eq!(host_header(ipv4, Scheme::Https, Some("example.com")), "example.com:80");
+        assert_eq!(host_header(ipv6, Scheme::Https, None), "[::1]");
+        assert_eq!(host_header(ipv6, Scheme::Http, None), "[::1]:443");
+    }
+
+    #[test]
+    fn base64_pads_like_python() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn murmur3_matches_the_reference() {
+        assert_eq!(murmur3_32(b"", 0), 0);
+        assert_eq!(murmur3_32(b"", 1), 0x514e28b7);
+        assert_eq!(murmur3_32(b"Hello, world!", 0x9747b28c), 0x24884cba);
+        assert_eq!(murmur3_32(b"The quick brown fox jumps over the lazy dog", 0x9747b28c), 0x2fa826cd);
+        // mmh3.hash("foo")
+        assert_eq!(murmur3_32(b"foo", 0) as i32, -156908512);
+    }
+
+    #[test]
+    fn favicon_hash_wraps_the_base64_lines() {
+        assert_eq!(favicon_hash(&icon()), ICON_HASH);
+        assert_eq!(favicon_hash(b""), 0);
+    }
+
+    #[tokio::test]
+    async fn fingerprint_http_of_a_stand_in_server() {
+        let port = http_server(PAGE).await;
+        let info = fingerprint_http(LOCALHOST, port, Scheme::Http, None, TEST_TIMEOUT).await.unwrap();
+        assert_eq!(info, HttpInfo {
+            status_code: 200,
+            server: Some(String::from("nginx/1.18.0 (Ubuntu)")),
+            title: Some(String::from("Router & Modem")),
+            location: None,
+            favicon_hash: Some(ICON_HASH),
+        });
+    }
+
+    #[tokio::test]
+    async fn fingerprint_http_keeps_the_redirect() {
+        let port = http_server(b"HTTP/1.1 302 Found\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n").await;
+        let info = fingerprint_http(LOCALHOST, port, Scheme::Http, None, TEST_TIMEOUT).await.unwrap();
+        assert_eq!((info.status_code, info.location.as_deref(), info.title), (302, Some("/login"), None));
+    }
+
+    #[tokio::test]
+    async fn fingerprint_https_ports() {
+        let https = https_server(PAGE).await;
+        let http = http_server(PAGE).await;
+        let permits = Semaphore::new(4);
+        let infos = fingerprint_http_ports(LOCALHOST, &[(https, Scheme::Https), (http, Scheme::Https)], Some("example.com"), TEST_TIMEOUT, &permits).await;
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].0, https);
+        assert_eq!(infos[0].1.title.as_deref(), Some("Router & Modem"));
+        assert_eq!(infos[0].1.favicon_hash, Some(ICON_HASH));
+    }
+}
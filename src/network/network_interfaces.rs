@@ -0,0 +1,210 @@
+use std::net::IpAddr;
+
+use ipnet::{IpNet, Ipv4Net};
+use pnet::datalink::{self, NetworkInterface};
+use serde::{Serialize, Deserialize};
+
+use crate::error::ScanError;
+
+// Networks larger than this are not scanned by `--local`, a /16 already has 65534 hosts
+pub const MIN_LOCAL_IPV4_PREFIX: u8 = 16;
+
+// An address of an interface together with the prefix length of its network
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+impl InterfaceAddress {
+    pub fn network(&self) -> Option<IpNet> {
+        IpNet::new(self.ip, self.prefix).ok().map(|network| network.trunc())
+    }
+
+    // True if the address is in the same network, i.e. reachable without a router
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.network().is_some_and(|network| network.contains(&ip))
+    }
+
+    // Link-local IPv6 addresses (fe80::/10) need a scope id to be used as a target
+    pub fn is_link_local(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ipv4) => ipv4.is_link_local(),
+            IpAddr::V6(ipv6) => (ipv6.segments()[0] & 0xffc0) == 0xfe80,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub description: String,
+    pub mac_address: Option<String>,
+    pub addresses: Vec<InterfaceAddress>,
+    // Names of the set flags like ifconfig shows them, e.g. UP, BROADCAST, RUNNING
+    pub flags: Vec<String>,
+    pub is_up: bool,
+    pub is_loopback: bool,
+    // Only known on Linux
+    pub mtu: Option<u32>,
+}
+
+impl InterfaceInfo {
+    fn from_pnet(interface: &NetworkInterface) -> Self {
+        let flags: Vec<String> = [
+            ("UP", interface.is_up()),
+            ("BROADCAST", interface.is_broadcast()),
+            ("LOOPBACK", interface.is_loopback()),
+            ("POINTOPOINT", interface.is_point_to_point()),
+            ("RUNNING", interface.is_running()),
+            ("MULTICAST", interface.is_multicast()),
+            ("LOWER_UP", interface.is_lower_up()),
+            ("DORMANT", interface.is_dormant()),
+        ]
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+        InterfaceInfo {
+            name: interface.name.clone(),
+            description: interface.description.clone(),
+            mac_address: interface.mac.map(|mac| mac.to_string()),
+            addresses: interface.ips.iter().map(|network| InterfaceAddress { ip: network.ip(), prefix: network.prefix() }).collect(),
+            flags,
+            is_up: interface.is_up(),
+            is_loopback: interface.is_loopback(),
+            mtu: read_mtu(&interface.name),
+        }
+    }
+
+    // The IPv4 networks attached to this interface, with the host bits of the own address cleared
+    pub fn ipv4_networks(&self) -> Vec<Ipv4Net> {
+        self.addresses.iter()
+            .filter_map(|address| match address.network() {
+                Some(IpNet::V4(network)) if network.prefix_len() > 0 && network.prefix_len() < 32 => Some(network),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// All interfaces of the host
+pub fn analyse_interfaces() -> Vec<InterfaceInfo> {
+    datalink::interfaces().iter().map(InterfaceInfo::from_pnet).collect()
+}
+
+// Addresses of the non-loopback interfaces, the hosts in their networks are reachable without a router
+pub fn local_addresses(interfaces: &[InterfaceInfo]) -> Vec<InterfaceAddress> {
+    interfaces.iter()
+        .filter(|interface| !interface.is_loopback)
+        .flat_map(|interface| interface.addresses.iter().copied())
+        .filter(|address| address.prefix > 0)
+        .collect()
+}
+
+// IPv4 networks of all non-loopback interfaces that are up, or of the one given by name. Networks larger
+// than a /16 are skipped unless the interface is named explicitly, they would take ages to scan
+pub fn attached_networks(interfaces: &[InterfaceInfo], name: Option<&str>) -> Result<Vec<Ipv4Net>, ScanError> {
+    let Some(name) = name else {
+        let mut networks: Vec<Ipv4Net> = interfaces.iter()
+            .filter(|interface| interface.is_up && !interface.is_loopback)
+            .flat_map(|interface| interface.ipv4_networks())
+            .filter(|network| network.prefix_len() >= MIN_LOCAL_IPV4_PREFIX)
+            .collect();
+        networks.sort();
+        networks.dedup();
+        return Ok(networks);
+    };
+
+    let Some(interface) = interfaces.iter().find(|interface| interface.name == name) else {
+        let names: Vec<&str> = interfaces.iter().map(|interface| interface.name.as_str()).collect();
+        return Err(ScanError::InvalidTarget {
+            target: name.to_string(),
+            reason: format!("no such interface, available are {}", names.join(", ")),
+        });
+    };
+    let networks = interface.ipv4_networks();
+    if networks.is_empty() {
+        return Err(ScanError::InvalidTarget {
+            target: name.to_string(),
+            reason: String::from("the interface has no IPv4 network"),
+        });
+    }
+    Ok(networks)
+}
+
+#[cfg(target_os = "linux")]
+fn read_mtu(name: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/mtu", name)).ok()?.trim().parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_mtu(_name: &str) -> Option<u32> {
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, addresses: &[&str], is_up: bool, is_loopback: bool) -> InterfaceInfo {
+        InterfaceInfo {
+            name: name.to_string(),
+            description: String::new(),
+            mac_address: None,
+            addresses: addresses.iter()
+                .map(|address| {
+                    let network: IpNet = address.parse().unwrap();
+                    InterfaceAddress { ip: network.addr(), prefix: network.prefix_len() }
+                })
+                .collect(),
+            flags: Vec::new(),
+            is_up,
+            is_loopback,
+            mtu: None,
+        }
+    }
+
+    fn interfaces() -> Vec<InterfaceInfo> {
+        vec![
+            interface("lo", &["127.0.0.1/8", "::1/128"], true, true),
+            interface("eth0", &["192.168.0.7/24", "fd00::7/64"], true, false),
+            interface("eth1", &["10.0.0.2/8"], true, false),
+            interface("wlan0", &["172.16.5.9/24"], false, false),
+            interface("tun0", &["10.8.0.1/32"], true, false),
+        ]
+    }
+
+    #[test]
+    fn interface_address_knows_its_network() {
+        let address = InterfaceAddress { ip: "192.168.0.7".parse().unwrap(), prefix: 24 };
+        assert_eq!(address.network(), Some("192.168.0.0/24".parse().unwrap()));
+        assert!(address.contains("192.168.0.200".parse().unwrap()));
+        assert!(!address.contains("192.168.1.1".parse().unwrap()));
+        assert!(InterfaceAddress { ip: "fe80::1".parse().unwrap(), prefix: 64 }.is_link_local());
+        assert!(!InterfaceAddress { ip: "fd00::1".parse().unwrap(), prefix: 64 }.is_link_local());
+    }
+
+    #[test]
+    fn attached_networks_skips_loopback_down_and_large_networks() {
+        let networks = attached_networks(&interfaces(), None).unwrap();
+        assert_eq!(networks, vec!["192.168.0.0/24".parse::<Ipv4Net>().unwrap()]);
+    }
+
+    #[test]
+    fn attached_networks_of_a_named_interface_ignore_the_size_limit() {
+        assert_eq!(attached_networks(&interfaces(), Some("eth1")).unwrap(), vec!["10.0.0.0/8".parse::<Ipv4Net>().unwrap()]);
+        assert!(matches!(attached_networks(&interfaces(), Some("eth9")), Err(ScanError::InvalidTarget { reason, .. }) if reason.contains("eth0")));
+        // A /32 has no other hosts to scan
+        assert!(attached_networks(&interfaces(), Some("tun0")).is_err());
+    }
+
+    #[test]
+    fn local_addresses_leave_out_loopback() {
+        let addresses = local_addresses(&interfaces());
+        assert!(addresses.iter().all(|address| !address.ip.is_loopback()));
+        assert_eq!(addresses.len(), 5);
+    }
+}
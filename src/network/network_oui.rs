@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::ScanError;
+
+// The IEEE MA-L registry, compacted by build.rs from data/oui.csv
+const IEEE_OUI_TABLE: &str = include_str!(concat!(env!("OUT_DIR"), "/oui_table.txt"));
+// Common prefixes outside the registry, see the header of the file
+const EXTRA_OUI_TABLE: &str = include_str!("oui_table.txt");
+
+// Maps the first three bytes of a MAC address, the IEEE Organizationally Unique Identifier, to the vendor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OuiTable {
+    vendors: HashMap<[u8; 3], String>,
+}
+
+impl OuiTable {
+    pub fn embedded() -> Self {
+        let mut table = OuiTable::default();
+        table.merge(IEEE_OUI_TABLE);
+        table.merge(EXTRA_OUI_TABLE);
+        table
+    }
+
+    // The embedded table, with the entries of the file taking precedence.
+    // Reads the IEEE oui.txt, nmap-mac-prefixes and Wireshark manuf formats
+    pub fn with_file(path: &Path) -> Result<Self, ScanError> {
+        let mut table = Self::embedded();
+        table.merge(&fs::read_to_string(path)?);
+        Ok(table)
+    }
+
+    pub fn lookup(&self, mac: &str) -> Option<&str> {
+        let prefix = parse_prefix(mac)?;
+        self.vendors.get(&prefix).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vendors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vendors.is_empty()
+    }
+
+    fn merge(&mut self, text: &str) {
+        for line in text.lines() {
+            if let Some((prefix, vendor)) = parse_line(line) {
+                self.vendors.insert(prefix, vendor);
+            }
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<([u8; 3], String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    // IEEE oui.txt: "00-00-0C   (hex)\t\tCisco Systems, Inc", the "(base 16)" lines repeat the same entry
+    if let Some((prefix, vendor)) = line.split_once("(hex)") {
+        return Some((parse_prefix(prefix.trim())?, vendor.trim().to_string()));
+    }
+
+    // nmap-mac-prefixes: "00000C Cisco Systems"
+    // Wireshark manuf: "00:00:0C\tCisco\tCisco Systems, Inc", the last column is the full name.
+    // Longer prefixes like "00:1B:C5:00:00:00/36" belong to smaller blocks and are skipped
+    let (prefix, vendor) = line.split_once(char::is_whitespace)?;
+    if prefix.contains('/') {
+        return None;
+    }
+    let vendor = vendor.split('\t').map(str::trim).rfind(|column| !column.is_empty())?;
+    Some((parse_prefix(prefix)?, vendor.to_string()))
+}
+
+// First three bytes of "00:00:0c:...", "00-00-0C" or "00000C"
+fn parse_prefix(text: &str) -> Option<[u8; 3]> {
+    let hex: String = text.chars().filter(|c| !matches!(c, ':' | '-' | '.')).take(6).collect();
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(&hex, 16).ok()?;
+    let [_, a, b, c] = value.to_be_bytes();
+    Some([a, b, c])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_table_holds_the_ieee_registry() {
+        let table = OuiTable::embedded();
+        assert!(table.len() > 30000);
+        assert_eq!(table.lookup("00:00:0c:12:34:56"), Some("Cisco Systems, Inc"));
+        assert_eq!(table.lookup("B8-27-EB-12-34-56"), Some("Raspberry Pi Foundation"));
+        assert_eq!(table.lookup("24:0a:c4:00:00:01"), Some("Espressif Inc."));
+        assert_eq!(table.lookup("52:54:00:12:34:56"), Some("QEMU virtual NIC"));
+        assert_eq!(table.lookup("02:00:00:00:00:01"), None);
+    }
+
+    #[test]
+    fn parse_line_reads_all_supported_formats() {
+        let cisco: Option<([u8; 3], String)> = Some(([0x00, 0x00, 0x0c], String::from("Cisco Systems, Inc")));
+        assert_eq!(parse_line("00-00-0C   (hex)\t\tCisco Systems, Inc"), cisco);
+        assert_eq!(parse_line("00000C Cisco Systems, Inc"), cisco);
+        assert_eq!(parse_line("00:00:0C\tCisco\tCisco Systems, Inc"), cisco);
+        assert_eq!(parse_line("00:1B:C5:00:00:00/36\tConverg\tConverging Systems Inc."), None);
+        // The line after the "(hex)" one in oui.txt, it parses to the same entry
+        assert_eq!(parse_line("00000C     (base 16)\t\tCisco Systems, Inc"), cisco);
+        assert_eq!(parse_line("# comment"), None);
+        assert_eq!(parse_line(""), None);
+    }
+
+    #[test]
+    fn parse_prefix_accepts_the_common_notations() {
+        for mac in ["b8:27:eb:01:02:03", "B8-27-EB", "b827eb", "b827.eb01.0203"] {
+            assert_eq!(parse_prefix(mac), Some([0xb8, 0x27, 0xeb]), "{}", mac);
+        }
+        assert_eq!(parse_prefix("b8:27"), None);
+        assert_eq!(parse_prefix("zz:27:eb"), None);
+    }
+
+    #[test]
+    fn file_entries_take_precedence() {
+        let path = std::env::temp_dir().join(format!("network_scanner_oui_{}.txt", std::process::id()));
+        fs::write(&path, "00000C Our Switch\n020000 Lab devices\n").unwrap();
+        let table = OuiTable::with_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        let table = table.unwrap();
+        assert_eq!(table.lookup("00:00:0c:00:00:01"), Some("Our Switch"));
+        assert_eq!(table.lookup("02:00:00:00:00:01"), Some("Lab devices"));
+        assert_eq!(table.len(), OuiTable::embedded().len() + 1);
+    }
+}
@@ -0,0 +1,477 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+
+use crate::network::network_http::HTTP_PORTS;
+use crate::network::network_tls::{client_hello, TLS_PORTS};
+
+// Time to wait for the first byte of a banner or of the reply to a probe
+pub const DEFAULT_SERVICE_TIMEOUT: Duration = Duration::from_secs(2);
+// Once a reply started, the rest of it is read until the peer is quiet for this long
+const REPLY_GRACE: Duration = Duration::from_millis(100);
+// Enough for a banner, the headers of an HTTP response or the server section of Redis INFO
+const MAX_REPLY_LEN: usize = 8192;
+// The banner is kept for reference, but only its first line and only this many characters
+const MAX_BANNER_LEN: usize = 128;
+// Upper bound of simultaneously probed ports per host, every probe holds a connection
+const MAX_SERVICES_IN_FLIGHT: usize = 16;
+// Name sent with SMTP EHLO
+const EHLO_NAME: &str = "network-scanner";
+
+// Ports where the client speaks first, so they get their probe before waiting for a banner
+const REDIS_PORTS: [u16; 1] = [6379];
+
+// Service found on an open port by its banner or its reply to one of the probes, like `nmap -sV`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ServiceInfo {
+    // nmap's service name, e.g. "ssh" or "http", "unknown" if the reply matched nothing
+    pub name: String,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    // First line of the banner or the reply, printable characters only
+    pub banner: Option<String>,
+}
+
+impl ServiceInfo {
+    // Product and version are cut out of the reply of the host, they are cleaned up like the banner
+    fn new(name: &str, product: Option<String>, version: Option<String>, reply: &[u8]) -> Self {
+        ServiceInfo {
+            name: name.to_string(),
+            product: product.as_deref().and_then(printable),
+            version: version.as_deref().and_then(printable),
+            banner: first_line(reply),
+        }
+    }
+}
+
+// Payloads sent to an open port, modelled after the probes of nmap-service-probes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Probe {
+    // Just connect and listen, for the many protocols where the server speaks first (SSH, FTP, SMTP, ...)
+    Null,
+    TlsClientHello,
+    HttpHead,
+    RedisPing,
+}
+
+impl Probe {
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Probe::Null => Vec::new(),
+            Probe::TlsClientHello => client_hello(true, None),
+            Probe::HttpHead => b"HEAD / HTTP/1.0\r\n\r\n".to_vec(),
+            Probe::RedisPing => b"PING\r\n".to_vec(),
+        }
+    }
+
+    fn ports(&self) -> &'static [u16] {
+        match self {
+            Probe::Null => &[],
+            Probe::TlsClientHello => &TLS_PORTS,
+            Probe::HttpHead => &HTTP_PORTS,
+            Probe::RedisPing => &REDIS_PORTS,
+        }
+    }
+}
+
+const PROBES: [Probe; 4] = [Probe::Null, Probe::TlsClientHello, Probe::HttpHead, Probe::RedisPing];
+
+// Probes in the order they are tried on a port: the ones meant for the port first, then waiting for a banner, then the rest
+fn probes_for(port: u16) -> Vec<Probe> {
+    let (mut likely, rest): (Vec<Probe>, Vec<Probe>) = PROBES.iter().partition(|probe| probe.ports().contains(&port));
+    likely.extend(rest);
+    likely
+}
+
+// Identify the services on the open ports of a host, ports without any reply are left out.
+// Every probe needs one of the permits shared by all hosts of the scan
+pub async fn detect_services(ip: IpAddr, ports: &[u16], timeout: Duration, permits: &Semaphore) -> Vec<(u16, ServiceInfo)> {
+    let mut services: Vec<(u16, ServiceInfo)> = stream::iter(ports.iter().copied())
+        .map(|port| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            detect_service(ip, port, timeout).await.map(|service| (port, service))
+        })
+        .buffer_unordered(MAX_SERVICES_IN_FLIGHT)
+        .filter_map(|service| async move { service })
+        .collect()
+        .await;
+    services.sort_unstable_by_key(|(port, _)| *port);
+    services
+}
+
+// Try the probes one after the other, each on a fresh connection, until a reply is recognized.
+// A reply that matched nothing is reported as "unknown" if no other probe does better
+pub async fn detect_service(ip: IpAddr, port: u16, timeout: Duration) -> Option<ServiceInfo> {
+    let address = SocketAddr::new(ip, port);
+    let mut unknown: Option<ServiceInfo> = None;
+
+    for probe in probes_for(port) {
+        let Ok(Ok(mut stream)) = tokio::time::timeout(timeout, TcpStream::connect(address)).await else {
+            // The port closed in the meantime or we ran out of sockets, no use in trying further
+            break;
+        };
+        let payload = probe.payload();
+        if !payload.is_empty() && stream.write_all(&payload).await.is_err() {
+            continue;
+        }
+        let Some(reply) = read_reply(&mut stream, timeout).await else {
+            continue;
+        };
+        match identify(&reply) {
+            Some(service) => return Some(follow_up(&mut stream, service, &reply, timeout).await),
+            None => {
+                unknown.get_or_insert_with(|| ServiceInfo::new("unknown", None, None, &reply));
+            },
+        }
+    }
+    unknown
+}
+
+// Read until the peer closes the connection, is quiet for a moment or the buffer is full. None if nothing came back in time
+async fn read_reply(stream: &mut TcpStream, timeout: Duration) -> Option<Vec<u8>> {
+    let mut reply = vec![0u8; MAX_REPLY_LEN];
+    let mut len = 0;
+    let mut wait = timeout;
+    while len < MAX_REPLY_LEN {
+        match tokio::time::timeout(wait, stream.read(&mut reply[len..])).await {
+            Ok(Ok(n)) if n > 0 => {
+                len += n;
+                wait = REPLY_GRACE;
+            },
+            _ => break,
+        }
+    }
+    reply.truncate(len);
+    (len > 0).then_some(reply)
+}
+
+async fn exchange(stream: &mut TcpStream, request: &[u8], timeout: Duration) -> Option<Vec<u8>> {
+    stream.write_all(request).await.ok()?;
+    read_reply(stream, timeout).await
+}
+
+// Some protocols need a second request on the same connection to tell them apart or to learn the version
+async fn follow_up(stream: &mut TcpStream, mut service: ServiceInfo, reply: &[u8], timeout: Duration) -> ServiceInfo {
+    match service.name.as_str() {
+        // A bare "220" greeting is used by FTP and SMTP alike, only an SMTP server accepts EHLO
+        "ftp" | "smtp" if service.product.is_none() => {
+            let request = format!("EHLO {}\r\n", EHLO_NAME);
+            if let Some(ehlo) = exchange(stream, request.as_bytes(), timeout).await {
+                service.name = String::from(if ehlo.starts_with(b"250") { "smtp" } else { "ftp" });
+            }
+        },
+        // PING only proves it's Redis, the version is part of INFO
+        "redis" if reply.starts_with(b"+PONG") => {
+            if let Some(info) = exchange(stream, b"INFO server\r\n", timeout).await {
+                let info = String::from_utf8_lossy(&info);
+                service.product = Some(String::from("Redis key-value store"));
+                service.version = info.lines().find_map(|line| line.strip_prefix("redis_version:")).and_then(printable);
+            }
+        },
+        _ => {},
+    }
+    service
+}
+
+// Match a banner or a reply against the signatures of the supported protocols
+fn identify(reply: &[u8]) -> Option<ServiceInfo> {
+    let text = String::from_utf8_lossy(reply);
+
+    if let Some(rest) = text.strip_prefix("SSH-") {
+        // "SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6", the software version ends at the first space
+        let software = rest.split_once('-').map(|(_, software)| software).unwrap_or(rest);
+        let software = software.split_whitespace().next().unwrap_or_default();
+        let (product, version) = match software.split_once('_') {
+            Some((product, version)) => (product, Some(version.to_string())),
+            None => (software, None),
+        };
+        return Some(ServiceInfo::new("ssh", Some(product.to_string()).filter(|product| !product.is_empty()), version, reply));
+    }
+    if let Some(rest) = text.strip_prefix("HTTP/") {
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            // "Server: nginx/1.18.0 (Ubuntu)"
+            let server = text.lines()
+                .take_while(|line| !line.is_empty())
+                .find_map(|line| line.split_once(':').filter(|(name, _)| name.eq_ignore_ascii_case("server")).map(|(_, value)| value.trim()));
+            let (product, version) = match server.and_then(|server| server.split_whitespace().next()) {
+                Some(token) => match token.split_once('/') {
+                    Some((product, version)) => (Some(product.to_string()), Some(version.to_string())),
+                    None => (Some(token.to_string()), None),
+                },
+                None => (None, None),
+            };
+            return Some(ServiceInfo::new("http", product, version, reply));
+        }
+    }
+    // TLS handshake or alert record, the server answered the ClientHello
+    if reply.len() >= 3 && (reply[0] == 0x16 || reply[0] == 0x15) && reply[1] == 0x03 && reply[2] <= 0x04 {
+        return Some(ServiceInfo::new("ssl", None, None, &[]));
+    }
+    if reply.starts_with(b"+PONG") || reply.starts_with(b"-NOAUTH") || reply.starts_with(b"-DENIED") || reply.starts_with(b"-ERR wrong number") {
+        return Some(ServiceInfo::new("redis", None, None, reply));
+    }
+    if let Some(version) = text.strip_prefix("RFB ") {
+        // "RFB 003.008", the protocol version
+        let version = version.trim().split('.').map(|part| part.trim_start_matches('0')).map(|part| if part.is_empty() { "0" } else { part }).collect::<Vec<&str>>().join(".");
+        return Some(ServiceInfo::new("vnc", None, Some(version), reply));
+    }
+    if let Some(service) = identify_mysql(reply) {
+        return Some(service);
+    }
+    if text.starts_with("220") {
+        if text.contains("FTP") {
+            let (product, version) = product_version(&text, &[("vsFTPd", "vsftpd"), ("ProFTPD", "ProFTPD"), ("Pure-FTPd", "Pure-FTPd"), ("FileZilla Server", "FileZilla ftpd"), ("Microsoft FTP Service", "Microsoft ftpd")]);
+            return Some(ServiceInfo::new("ftp", product, version, reply));
+        }
+        let (product, version) = product_version(&text, &[("Postfix", "Postfix smtpd"), ("Exim", "Exim smtpd"), ("Sendmail", "Sendmail"), ("Microsoft ESMTP MAIL Service", "Microsoft Exchange smtpd"), ("OpenSMTPD", "OpenSMTPD")]);
+        let name = if text.contains("SMTP") || product.is_some() { "smtp" } else { "ftp" };
+        return Some(ServiceInfo::new(name, product, version, reply));
+    }
+    if text.starts_with("+OK") {
+        let (product, version) = product_version(&text, &[("Dovecot", "Dovecot pop3d"), ("Cyrus", "Cyrus pop3d"), ("Courier", "Courier pop3d")]);
+        return Some(ServiceInfo::new("pop3", product, version, reply));
+    }
+    if text.starts_with("* OK") {
+        let (product, version) = product_version(&text, &[("Dovecot", "Dovecot imapd"), ("Cyrus IMAP", "Cyrus imapd"), ("Courier-IMAP", "Courier imapd")]);
+        return Some(ServiceInfo::new("imap", product, version, reply));
+    }
+    // Telnet servers start with option negotiation, IAC DO/DONT/WILL/WONT
+    if reply.len() >= 3 && reply[0] == 0xff && (0xfb..=0xfe).contains(&reply[1]) {
+        return Some(ServiceInfo::new("telnet", None, None, &[]));
+    }
+    None
+}
+
+// Initial handshake packet of MySQL and MariaDB: 3 bytes length, sequence 0, protocol 10, NUL terminated version
+fn identify_mysql(reply: &[u8]) -> Option<ServiceInfo> {
+    if reply.len() < 6 || reply[3] != 0 {
+        return None;
+    }
+    let len = u32::from_le_bytes([reply[0], reply[1], reply[2], 0]) as usize;
+    if !(2..=MAX_REPLY_LEN).contains(&len) {
+        return None;
+    }
+    match reply[4] {
+        0x0a => {
+            let end = reply[5..].iter().position(|byte| *byte == 0)?;
+            let version = std::str::from_utf8(&reply[5..5 + end]).ok()?;
+            if !version.starts_with(|c: char| c.is_ascii_digit()) {
+                return None;
+            }
+            // MariaDB prefixes its version with "5.5.5-" for old clients, e.g. "5.5.5-10.6.12-MariaDB-0ubuntu0.22.04.1"
+            let service = match version.find("-MariaDB") {
+                Some(end) => ServiceInfo::new("mysql", Some(String::from("MariaDB")), Some(version[..end].trim_start_matches("5.5.5-").to_string()), &[]),
+                None => ServiceInfo::new("mysql", Some(String::from("MySQL")), Some(version.to_string()), &[]),
+            };
+            Some(service)
+        },
+        // Error packet, e.g. "Host '10.0.0.1' is not allowed to connect to this MySQL server"
+        0xff if String::from_utf8_lossy(reply).contains("MySQL") || String::from_utf8_lossy(reply).contains("MariaDB") => {
+            Some(ServiceInfo::new("mysql", None, None, &reply[7.min(reply.len())..]))
+        },
+        _ => None,
+    }
+}
+
+// First product of the list found in the text, with the version if one follows it, e.g. "ESMTP Exim 4.92 Tue, ..." gives 4.92
+fn product_version(text: &str, products: &[(&str, &str)]) -> (Option<String>, Option<String>) {
+    for (needle, product) in products {
+        let Some(start) = text.find(needle) else {
+            continue;
+        };
+        let rest = text[start + needle.len()..].trim_start_matches([' ', '/', '_', 'v']);
+        let version: String = rest.chars().take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')).collect();
+        let version = version.starts_with(|c: char| c.is_ascii_digit()).then_some(version);
+        return (Some(product.to_string()), version);
+    }
+    (None, None)
+}
+
+fn first_line(reply: &[u8]) -> Option<String> {
+    printable(String::from_utf8_lossy(reply).lines().next()?)
+}
+
+// Text of a remote host without control characters, so it can't smuggle terminal escapes into a report. None if nothing is left
+fn printable(text: &str) -> Option<String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).take(MAX_BANNER_LEN).collect();
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpListener;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+    // Stand-in server that sends the banner right after accepting and answers every request with its reply
+    async fn server(banner: &'static [u8], reply: &'static [u8]) -> u16 {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    if !banner.is_empty() && stream.write_all(banner).await.is_err() {
+                        return;
+                    }
+                    let mut buffer = [0u8; 1024];
+                    while let Ok(n) = stream.read(&mut buffer).await {
+                        if n == 0 || reply.is_empty() || stream.write_all(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn identify_ssh_banner() {
+        let service = identify(b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n").unwrap();
+        assert_eq!(service.name, "ssh");
+        assert_eq!(service.product.as_deref(), Some("OpenSSH"));
+        assert_eq!(service.version.as_deref(), Some("8.9p1"));
+        assert_eq!(service.banner.as_deref(), Some("SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6"));
+    }
+
+    #[test]
+    fn identify_http_server_header() {
+        let service = identify(b"HTTP/1.1 200 OK\r\nserver: nginx/1.18.0 (Ubuntu)\r\n\r\n").unwrap();
+        assert_eq!(service.name, "http");
+        assert_eq!(service.product.as_deref(), Some("nginx"));
+        assert_eq!(service.version.as_deref(), Some("1.18.0"));
+
+        let service = identify(b"HTTP/1.0 404 Not Found\r\n\r\nServer: in the body/1.0").unwrap();
+        assert_eq!(service.product, None);
+    }
+
+    #[test]
+    fn identify_ftp_and_smtp_greetings() {
+        let service = identify(b"220 (vsFTPd 3.0.5)\r\n").unwrap();
+        assert_eq!((service.name.as_str(), service.product.as_deref(), service.version.as_deref()), ("ftp", Some("vsftpd"), Some("3.0.5")));
+
+        let service = identify(b"220 mail.example.com ESMTP Exim 4.92 Tue, 01 May 2024\r\n").unwrap();
+        assert_eq!((service.name.as_str(), service.product.as_deref(), service.version.as_deref()), ("smtp", Some("Exim smtpd"), Some("4.92")));
+
+        // Nothing to tell them apart, the EHLO follow-up decides
+        let service = identify(b"220 Welcome\r\n").unwrap();
+        assert_eq!((service.name.as_str(), service.product), ("ftp", None));
+    }
+
+    #[test]
+    fn identify_redis_vnc_mail_and_telnet() {
+        assert_eq!(identify(b"+PONG\r\n").unwrap().name, "redis");
+        assert_eq!(identify(b"-NOAUTH Authentication required.\r\n").unwrap().name, "redis");
+
+        let service = identify(b"RFB 003.008\n").unwrap();
+        assert_eq!((service.name.as_str(), service.version.as_deref()), ("vnc", Some("3.8")));
+
+        let service = identify(b"+OK Dovecot ready.\r\n").unwrap();
+        assert_eq!((service.name.as_str(), service.product.as_deref()), ("pop3", Some("Dovecot pop3d")));
+        assert_eq!(identify(b"* OK [CAPABILITY IMAP4rev1] Dovecot ready.\r\n").unwrap().name, "imap");
+
+        let service = identify(&[0xff, 0xfd, 0x18, 0xff, 0xfd, 0x20]).unwrap();
+        assert_eq!((service.name.as_str(), service.banner), ("telnet", None));
+
+        assert_eq!(identify(&[0x16, 0x03, 0x03, 0x00, 0x4a]).unwrap().name, "ssl");
+        assert_eq!(identify(b"hello there\r\n"), None);
+    }
+
+    #[test]
+    fn identify_mysql_and_mariadb_handshakes() {
+        fn handshake(version: &str) -> Vec<u8> {
+            let mut payload = vec![0x0a];
+            payload.extend_from_slice(version.as_bytes());
+            payload.extend_from_slice(&[0, 1, 0, 0, 0]);
+            let len = payload.len() as u32;
+            let mut packet = len.to_le_bytes()[..3].to_vec();
+            packet.push(0);
+            packet.extend(payload);
+            packet
+        }
+
+        let service = identify(&handshake("8.0.36-0ubuntu0.22.04.1")).unwrap();
+        assert_eq!((service.name.as_str(), service.product.as_deref(), service.version.as_deref()), ("mysql", Some("MySQL"), Some("8.0.36-0ubuntu0.22.04.1")));
+
+        let service = identify(&handshake("5.5.5-10.6.12-MariaDB-0ubuntu0.22.04.1")).unwrap();
+        assert_eq!((service.product.as_deref(), service.version.as_deref()), (Some("MariaDB"), Some("10.6.12")));
+
+        assert_eq!(identify_mysql(&handshake("not a version")), None);
+        assert_eq!(identify_mysql(&[0x05, 0x00, 0x00, 0x01, 0x0a, 0x00]), None);
+    }
+
+    #[test]
+    fn identify_strips_control_characters_from_product_and_version() {
+        let service = identify(b"SSH-2.0-Open\x1b[2JSSH_9.6\x07p1\r\n").unwrap();
+        assert_eq!((service.product.as_deref(), service.version.as_deref()), (Some("Open[2JSSH"), Some("9.6p1")));
+
+        let service = identify(b"HTTP/1.1 200 OK\r\nServer: \x1b]0;owned\x07nginx/1.18\x1b[0m\r\n\r\n").unwrap();
+        assert_eq!((service.product.as_deref(), service.version.as_deref()), (Some("]0;ownednginx"), Some("1.18[0m")));
+
+        let service = ServiceInfo::new("ftp", Some(String::from("\x1b\x07")), Some(String::from("1.3\x1b[1m")), b"");
+        assert_eq!((service.product, service.version.as_deref()), (None, Some("1.3[1m")));
+    }
+
+    #[test]
+    fn first_line_drops_control_characters() {
+        assert_eq!(first_line(b"\x1b[1mbanner\x07\r\nsecond line"), Some(String::from("[1mbanner")));
+        assert_eq!(first_line(b"\r\n"), None);
+        assert_eq!(first_line(&[b'a'; 300]).unwrap().len(), MAX_BANNER_LEN);
+    }
+
+    #[test]
+    fn probes_for_tries_the_port_probe_first() {
+        assert_eq!(probes_for(6379), [Probe::RedisPing, Probe::Null, Probe::TlsClientHello, Probe::HttpHead]);
+        assert_eq!(probes_for(443)[0], Probe::TlsClientHello);
+        assert_eq!(probes_for(2222), PROBES);
+    }
+
+    #[tokio::test]
+    async fn detect_service_reads_the_banner() {
+        let port = server(b"SSH-2.0-OpenSSH_9.6\r\n", b"").await;
+        let service = detect_service(LOCALHOST, port, TEST_TIMEOUT).await.unwrap();
+        assert_eq!((service.name.as_str(), service.product.as_deref(), service.version.as_deref()), ("ssh", Some("OpenSSH"), Some("9.6")));
+    }
+
+    #[tokio::test]
+    async fn detect_service_tells_smtp_from_ftp_with_ehlo() {
+        let port = server(b"220 Welcome\r\n", b"250-localhost\r\n250 HELP\r\n").await;
+        assert_eq!(detect_service(LOCALHOST, port, TEST_TIMEOUT).await.unwrap().name, "smtp");
+
+        let port = server(b"220 Welcome\r\n", b"530 Please login with USER and PASS.\r\n").await;
+        assert_eq!(detect_service(LOCALHOST, port, TEST_TIMEOUT).await.unwrap().name, "ftp");
+    }
+
+    #[tokio::test]
+    async fn detect_service_probes_silent_servers() {
+        let port = server(b"", b"HTTP/1.1 200 OK\r\nServer: Apache/2.4.58\r\n\r\n").await;
+        let service = detect_service(LOCALHOST, port, TEST_TIMEOUT).await.unwrap();
+        assert_eq!((service.name.as_str(), service.product.as_deref()), ("http", Some("Apache")));
+
+        let port = server(b"", b"").await;
+        assert_eq!(detect_service(LOCALHOST, port, TEST_TIMEOUT).await, None);
+    }
+
+    #[tokio::test]
+    async fn detect_services_reports_unknown_replies_and_skips_closed_ports() {
+        let port = server(b"hello there\r\n", b"").await;
+        let closed = {
+            let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let permits = Semaphore::new(4);
+        let services = detect_services(LOCALHOST, &[closed, port], TEST_TIMEOUT, &permits).await;
+        assert_eq!(services, vec![(port, ServiceInfo::new("unknown", None, None, b"hello there"))]);
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, StreamExt};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::tcp::{self, MutableTcpPacket, TcpFlags, TcpPacket};
+use pnet::transport::{self, tcp_packet_iter, TransportChannelType, TransportProtocol, TransportReceiver, TransportSender};
+use rand::random;
+use tokio::sync::{oneshot, Semaphore};
+
+use crate::network::network_core::{AdaptiveTimeout, PortState};
+
+// TCP header without options
+const SYN_PACKET_LEN: usize = 20;
+// Receive buffer of the raw socket
+const RECEIVE_BUFFER_LEN: usize = 65536;
+// How often the receiver wakes up to check if the scanner is gone
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+// A SYN without any answer is sent once more before the port counts as filtered, like nmap's default retries
+const SYN_ATTEMPTS: usize = 2;
+// Window size of the SYN, the same nmap uses
+const SYN_WINDOW: u16 = 1024;
+// Upper bound of unanswered SYNs per host, on top of the global limit
+const MAX_SYNS_IN_FLIGHT: usize = 256;
+
+// Probes waiting for an answer, by target address and port
+type Pending = Arc<Mutex<HashMap<(IpAddr, u16), oneshot::Sender<PortState>>>>;
+// SYN for the sender thread to put on the wire, with the channel the outcome of the send goes back on
+type Outgoing = ([u8; SYN_PACKET_LEN], IpAddr, oneshot::Sender<bool>);
+
+// Half-open TCP scan over raw sockets: send a SYN, a SYN-ACK means open, a RST closed and silence filtered.
+// The connection is never completed, the kernel resets it as it doesn't know about it. Needs root or CAP_NET_RAW
+pub struct SynScanner {
+    v4: Option<SynChannel>,
+    v6: Option<SynChannel>,
+    // Source port and initial sequence number of all SYNs, the answers are matched by them
+    source_port: u16,
+    sequence: u32,
+    stop: Arc<AtomicBool>,
+}
+
+// Raw TCP socket of one address family. Sending blocks, so the SYNs are sent and the answers read by threads of their own
+struct SynChannel {
+    outgoing: mpsc::Sender<Outgoing>,
+    pending: Pending,
+}
+
+impl fmt::Debug for SynScanner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SynScanner")
+            .field("ipv4", &self.v4.is_some())
+            .field("ipv6", &self.v6.is_some())
+            .field("source_port", &self.source_port)
+            .finish()
+    }
+}
+
+impl SynScanner {
+    // Open the raw sockets of the needed address families. None if raw sockets are not permitted,
+    // the caller falls back to the connect scan then
+    pub fn new(ipv4: bool, ipv6: bool) -> Option<Self> {
+        // Built before the sockets are opened, so if the second one fails, dropping the scanner stops the receiver of the first
+        let mut scanner = SynScanner {
+            v4: None,
+            v6: None,
+            // Ephemeral range, unlikely to collide with a port the kernel is using itself
+            source_port: 49152 + random::<u16>() % 16384,
+            sequence: random(),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        if ipv4 {
+            scanner.v4 = Some(SynChannel::open(TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp), scanner.source_port, scanner.sequence, &scanner.stop)?);
+        }
+        if ipv6 {
+            scanner.v6 = Some(SynChannel::open(TransportProtocol::Ipv6(IpNextHeaderProtocols::Tcp), scanner.source_port, scanner.sequence, &scanner.stop)?);
+        }
+        Some(scanner)
+    }
+
+    fn channel_for(&self, ip: IpAddr) -> Option<&SynChannel> {
+        match ip {
+            IpAddr::V4(_) => self.v4.as_ref(),
+            IpAddr::V6(_) => self.v6.as_ref(),
+        }
+    }
+
+    // State of a single port, None if the SYN could not be sent at all
+    async fn probe(&self, source: IpAddr, ip: IpAddr, port: u16, timeout: Duration, adaptive: Option<&AdaptiveTimeout>) -> Option<PortState> {
+        let channel = self.channel_for(ip)?;
+        let packet = syn_packet(source, ip, self.source_port, port, self.sequence);
+
+        for _ in 0..SYN_ATTEMPTS {
+            let (answer_sender, answer) = oneshot::channel();
+            lock(&channel.pending).insert((ip, port), answer_sender);
+
+            let start = Instant::now();
+            let (sent_sender, sent) = oneshot::channel();
+            if channel.outgoing.send((packet, ip, sent_sender)).is_err() || sent.await != Ok(true) {
+                lock(&channel.pending).remove(&(ip, port));
+                return None;
+            }
+            if let Ok(Ok(state)) = tokio::time::timeout(timeout, answer).await {
+                if let Some(adaptive) = adaptive {
+                    adaptive.observe(start.elapsed());
+                }
+                return Some(state);
+            }
+            lock(&channel.pending).remove(&(ip, port));
+        }
+        Some(PortState::Filtered)
+    }
+}
+
+impl Drop for SynScanner {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl SynChannel {
+    fn open(protocol: TransportProtocol, source_port: u16, sequence: u32, stop: &Arc<AtomicBool>) -> Option<Self> {
+        let (sender, receiver) = transport::transport_channel(RECEIVE_BUFFER_LEN, TransportChannelType::Layer4(protocol)).ok()?;
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+
+        let receiver_pending = Arc::clone(&pending);
+        let stop = Arc::clone(stop);
+        thread::spawn(move || receive_answers(receiver, receiver_pending, source_port, sequence, stop));
+        let (outgoing, packets) = mpsc::channel();
+        thread::spawn(move || send_packets(sender, packets));
+        Some(SynChannel { outgoing, pending })
+    }
+}
+
+// Send every SYN handed over by the probes, until the channel is dropped with the scanner
+fn send_packets(mut sender: TransportSender, packets: mpsc::Receiver<Outgoing>) {
+    for (packet, ip, sent) in packets {
+        let Some(packet) = TcpPacket::new(&packet) else {
+            continue;
+        };
+        let _ = sent.send(sender.send_to(packet, ip).is_ok());
+    }
+}
+
+// Hand every SYN-ACK and RST to our source port to the probe waiting for it, until the scanner is dropped
+fn receive_answers(mut receiver: TransportReceiver, pending: Pending, source_port: u16, sequence: u32, stop: Arc<AtomicBool>) {
+    let mut packets = tcp_packet_iter(&mut receiver);
+    while !stop.load(Ordering::Relaxed) {
+        let Ok(Some((packet, source))) = packets.next_with_timeout(RECEIVE_TIMEOUT) else {
+            continue;
+        };
+        // Answers to our SYN acknowledge its sequence number, anything else is other traffic
+        if packet.get_destination() != source_port || packet.get_acknowledgement() != sequence.wrapping_add(1) {
+            continue;
+        }
+        let flags = packet.get_flags();
+        let state = if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+            PortState::Open
+        } else if flags & TcpFlags::RST != 0 {
+            PortState::Closed
+        } else {
+            continue;
+        };
+        if let Some(answer) = lock(&pending).remove(&(source, packet.get_source())) {
+            let _ = answer.send(state);
+        }
+    }
+}
+
+fn syn_packet(source: IpAddr, destination: IpAddr, source_port: u16, port: u16, sequence: u32) -> [u8; SYN_PACKET_LEN] {
+    let mut buffer = [0u8; SYN_PACKET_LEN];
+    // Fixed size buffer, the packet always fits
+    let mut packet = MutableTcpPacket::new(&mut buffer).unwrap();
+    packet.set_source(source_port);
+    packet.set_destination(port);
+    packet.set_sequence(sequence);
+    packet.set_data_offset(5);
+    packet.set_flags(TcpFlags::SYN);
+    packet.set_window(SYN_WINDOW);
+
+    // The checksum covers a pseudo header with both addresses, the kernel doesn't fill it in for raw sockets
+    let checksum = match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => tcp::ipv4_checksum(&packet.to_immutable(), &source, &destination),
+        (IpAddr::V6(source), IpAddr::V6(destination)) => tcp::ipv6_checksum(&packet.to_immutable(), &source, &destination),
+        _ => 0,
+    };
+    packet.set_checksum(checksum);
+    buffer
+}
+
+// The address the kernel would send from to reach `ip`. Connecting a UDP socket picks the route without sending anything
+fn source_address(ip: IpAddr) -> Option<IpAddr> {
+    let unspecified: IpAddr = match ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).ok()?;
+    socket.connect(SocketAddr::new(ip, 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+// Whether raw TCP sockets may be opened, i.e. the SYN scan works instead of falling back to the connect scan
+pub fn syn_scan_permitted() -> bool {
+    transport::transport_channel(SYN_PACKET_LEN, TransportChannelType::Layer4(TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp))).is_ok()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// SYN scan of the ports of one host. Every unanswered SYN holds one of the permits shared by all hosts of the scan.
+// None if the host can't be scanned this way, e.g. no route to it, the caller falls back to the connect scan then
+pub async fn scan_ports_syn(scanner: &SynScanner, ip: IpAddr, timeout: Duration, ports: &[u16], permits: &Semaphore, adaptive: Option<&AdaptiveTimeout>) -> Option<Vec<(u16, PortState)>> {
+    scanner.channel_for(ip)?;
+    let source = source_address(ip)?;
+
+    let mut states: Vec<(u16, PortState)> = stream::iter(ports.iter().copied())
+        .map(|port| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            scanner.probe(source, ip, port, timeout, adaptive).await.map(|state| (port, state))
+        })
+        .buffer_unordered(MAX_SYNS_IN_FLIGHT)
+        .filter_map(|state| async move { state })
+        .collect()
+        .await;
+
+    // A SYN that could not be sent at all means the raw socket doesn't work for this host
+    if states.len() < ports.len() {
+        return None;
+    }
+    states.sort_unstable_by_key(|(port, _)| *port);
+    Some(states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[test]
+    fn syn_packet_carries_a_valid_checksum() {
+        let (source, destination) = (Ipv4Addr::new(192, 168, 0, 7), Ipv4Addr::new(192, 168, 0, 10));
+        let buffer = syn_packet(IpAddr::V4(source), IpAddr::V4(destination), 50000, 22, 0xdeadbeef);
+        let packet = TcpPacket::new(&buffer).unwrap();
+        assert_eq!((packet.get_source(), packet.get_destination()), (50000, 22));
+        assert_eq!(packet.get_sequence(), 0xdeadbeef);
+        assert_eq!((packet.get_flags(), packet.get_window(), packet.get_data_offset()), (TcpFlags::SYN, SYN_WINDOW, 5));
+        assert_eq!(packet.get_checksum(), tcp::ipv4_checksum(&packet, &source, &destination));
+        assert_ne!(packet.get_checksum(), 0);
+
+        let (source, destination) = (Ipv6Addr::LOCALHOST, "fd00::10".parse::<Ipv6Addr>().unwrap());
+        let buffer = syn_packet(IpAddr::V6(source), IpAddr::V6(destination), 50000, 443, 1);
+        let packet = TcpPacket::new(&buffer).unwrap();
+        assert_eq!(packet.get_checksum(), tcp::ipv6_checksum(&packet, &source, &destination));
+    }
+
+    #[test]
+    fn source_address_of_localhost() {
+        assert_eq!(source_address(LOCALHOST), Some(LOCALHOST));
+    }
+
+    #[test]
+    #[ignore = "needs root or CAP_NET_RAW"]
+    fn dropping_the_scanner_stops_the_receivers() {
+        let scanner = SynScanner::new(true, false).unwrap();
+        let stop = Arc::clone(&scanner.stop);
+        assert!(!stop.load(Ordering::Relaxed));
+        drop(scanner);
+        assert!(stop.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs root or CAP_NET_RAW"]
+    async fn scan_ports_syn_on_localhost() {
+        let scanner = SynScanner::new(true, false).unwrap();
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = TcpListener::bind((LOCALHOST, 0)).await.unwrap().local_addr().unwrap().port();
+        let permits = Semaphore::new(8);
+
+        let states = scan_ports_syn(&scanner, LOCALHOST, TEST_TIMEOUT, &[closed, open], &permits, None).await.unwrap();
+        let mut expected = vec![(open, PortState::Open), (closed, PortState::Closed)];
+        expected.sort_unstable_by_key(|(port, _)| *port);
+        assert_eq!(states, expected);
+
+        // No IPv6 channel was opened
+        assert_eq!(scan_ports_syn(&scanner, IpAddr::V6(Ipv6Addr::LOCALHOST), TEST_TIMEOUT, &[open], &permits, None).await, None);
+    }
+}
@@ -0,0 +1,368 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::time::Duration;
+
+use ipnet::{Ipv4Net, Ipv6Net};
+use tokio::net::lookup_host;
+
+use crate::error::ScanError;
+use crate::network::network_core::DnsClient;
+use crate::network::network_helpers::{ip_from_u128, ip_to_u128, validate_range};
+
+// The OS resolver has no timeout of its own, this leaves room for a few attempts of a slow nameserver
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Set of target addresses, stored as sorted, disjoint inclusive ranges so large networks stay cheap.
+// Overlapping and adjacent ranges are merged, which also removes duplicate addresses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TargetSet {
+    ranges: Vec<(IpAddr, IpAddr)>,
+}
+
+impl TargetSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_range(&mut self, start: IpAddr, end: IpAddr) {
+        self.extend([(start, end)]);
+    }
+
+    pub fn extend(&mut self, ranges: impl IntoIterator<Item = (IpAddr, IpAddr)>) {
+        self.ranges.extend(ranges);
+
+        // IpAddr orders all IPv4 before all IPv6 addresses, so sorting keeps the families apart
+        self.ranges.sort();
+        let mut merged: Vec<(IpAddr, IpAddr)> = Vec::with_capacity(self.ranges.len());
+        for (start, end) in self.ranges.drain(..) {
+            if let Some(last) = merged.last_mut() {
+                if last.1.is_ipv6() == start.is_ipv6() && ip_to_u128(start) <= ip_to_u128(last.1).saturating_add(1) {
+                    last.1 = last.1.max(end);
+                    continue;
+                }
+            }
+            merged.push((start, end));
+        }
+        self.ranges = merged;
+    }
+
+    pub fn remove_range(&mut self, start: IpAddr, end: IpAddr) {
+        let ipv6 = start.is_ipv6();
+        let mut remaining: Vec<(IpAddr, IpAddr)> = Vec::with_capacity(self.ranges.len() + 1);
+
+        for (range_start, range_end) in self.ranges.drain(..) {
+            if range_start.is_ipv6() != ipv6 || range_end < start || range_start > end {
+                remaining.push((range_start, range_end));
+                continue;
+            }
+            // Keep whatever sticks out on either side of the removed range
+            if range_start < start {
+                remaining.push((range_start, ip_from_u128(ip_to_u128(start) - 1, ipv6)));
+            }
+            if range_end > end {
+                remaining.push((ip_from_u128(ip_to_u128(end) + 1, ipv6), range_end));
+            }
+        }
+        self.ranges = remaining;
+    }
+
+    pub fn ranges(&self) -> &[(IpAddr, IpAddr)] {
+        &self.ranges
+    }
+
+    // Saturates at u64::MAX, IPv6 ranges that were not validated can hold up to 2^128 addresses
+    pub fn len(&self) -> u64 {
+        self.ranges.iter()
+            .map(|(start, end)| u64::try_from(ip_to_u128(*end) - ip_to_u128(*start)).map_or(u64::MAX, |len| len.saturating_add(1)))
+            .fold(0, u64::saturating_add)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|(start, end)| start.is_ipv6() == ip.is_ipv6() && *start <= ip && ip <= *end)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.ranges.iter().flat_map(|(start, end)| {
+            let ipv6 = start.is_ipv6();
+            (ip_to_u128(*start)..=ip_to_u128(*end)).map(move |value| ip_from_u128(value, ipv6))
+        })
+    }
+}
+
+fn parse_cidr(spec: &str) -> Option<(IpAddr, IpAddr)> {
+    // Try as Ipv4Net. The same hosts as Ipv4Net::hosts(), without network and broadcast address unless it's a /31 or /32,
+    // but computed from the bounds so even a /0 costs nothing
+    if let Ok(ipv4_net) = spec.parse::<Ipv4Net>() {
+        let (network, broadcast) = (u32::from(ipv4_net.network()), u32::from(ipv4_net.broadcast()));
+        let (first, last) = if ipv4_net.prefix_len() >= 31 { (network, broadcast) } else { (network + 1, broadcast - 1) };
+        return Some((IpAddr::V4(Ipv4Addr::from(first)), IpAddr::V4(Ipv4Addr::from(last))));
+    }
+
+    // Try as Ipv6Net, IPv6 has no broadcast address so every address of the network is a host
+    if let Ok(ipv6_net) = spec.parse::<Ipv6Net>() {
+        return Some((IpAddr::V6(ipv6_net.network()), IpAddr::V6(ipv6_net.broadcast())));
+    }
+
+    None
+}
+
+fn parse_octet(octet: &str) -> Option<u8> {
+    if octet.is_empty() || !octet.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    octet.parse::<u8>().ok()
+}
+
+fn parse_octet_range(spec: &str) -> Result<Option<Vec<(IpAddr, IpAddr)>>, ScanError> {
+    // nmap style octet ranges, e.g. 10.0.1-3.1-254, every octet is either a number or a range of numbers
+    let parts: Vec<&str> = spec.split('.').collect();
+    if parts.len() != 4 || !parts.iter().all(|part| part.chars().all(|c| c.is_ascii_digit() || c == '-')) {
+        return Ok(None);
+    }
+
+    let mut octets: Vec<(u8, u8)> = Vec::with_capacity(4);
+    for part in parts {
+        let (start, end) = part.split_once('-').unwrap_or((part, part));
+        let (Some(start), Some(end)) = (parse_octet(start), parse_octet(end)) else {
+            return Err(ScanError::InvalidTarget {
+                target: spec.to_string(),
+                reason: format!("'{}' is not an octet or octet range between 0 and 255", part),
+            });
+        };
+        if start > end {
+            return Err(ScanError::InvalidTarget {
+                target: spec.to_string(),
+                reason: format!("octet range '{}' is inverted", part),
+            });
+        }
+        octets.push((start, end));
+    }
+
+    // One range of the last octet for every combination of the first three
+    let mut ranges: Vec<(IpAddr, IpAddr)> = Vec::new();
+    for a in octets[0].0..=octets[0].1 {
+        for b in octets[1].0..=octets[1].1 {
+            for c in octets[2].0..=octets[2].1 {
+                ranges.push((
+                    IpAddr::V4(Ipv4Addr::new(a, b, c, octets[3].0)),
+                    IpAddr::V4(Ipv4Addr::new(a, b, c, octets[3].1)),
+                ));
+            }
+        }
+    }
+    Ok(Some(ranges))
+}
+
+fn parse_target(spec: &str) -> Result<Option<Vec<(IpAddr, IpAddr)>>, ScanError> {
+    // Parse a target given as address, CIDR, dash range or octet range. None if it's none of them, i.e. a hostname
+    if let Some(range) = parse_cidr(spec) {
+        return Ok(Some(vec![range]));
+    }
+
+    if let Ok(ip) = spec.parse::<IpAddr>() {
+        return Ok(Some(vec![(ip, ip)]));
+    }
+
+    // Range of two full addresses, e.g. 10.0.0.5-10.0.0.40
+    if let Some((start, end)) = spec.split_once('-') {
+        if let (Ok(start), Ok(end)) = (start.trim().parse::<IpAddr>(), end.trim().parse::<IpAddr>()) {
+            validate_range(start, end)?;
+            return Ok(Some(vec![(start, end)]));
+        }
+    }
+
+    parse_octet_range(spec)
+}
+
+async fn resolve_hostname(hostname: &str, dns: Option<&DnsClient>) -> Result<Vec<(IpAddr, IpAddr)>, ScanError> {
+    // Hostnames consist of letters, digits, dashes and dots. Anything else is a typo in an address
+    let is_hostname = hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        && hostname.chars().any(|c| c.is_ascii_alphabetic());
+    if !is_hostname {
+        return Err(ScanError::InvalidTarget {
+            target: hostname.to_string(),
+            reason: String::from("not an IP address, CIDR, range or hostname"),
+        });
+    }
+
+    // Every A and AAAA record of the host is a target, from the given nameservers or the OS resolver
+    let addresses: Result<Vec<IpAddr>, String> = match dns {
+        Some(dns) => dns.lookup_host(hostname).await.map_err(|e| e.to_string()),
+        None => match tokio::time::timeout(RESOLVE_TIMEOUT, lookup_host((hostname, 0))).await {
+            Ok(addresses) => addresses.map(|addresses| addresses.map(|address| address.ip()).collect()).map_err(|e| e.to_string()),
+            Err(_) => Err(String::from("timed out")),
+        },
+    };
+    match addresses {
+        Ok(addresses) => Ok(addresses.into_iter().map(|ip| (ip, ip)).collect()),
+        Err(e) => Err(ScanError::InvalidTarget {
+            target: hostname.to_string(),
+            reason: format!("failed to resolve hostname ({})", e),
+        }),
+    }
+}
+
+pub async fn parse_targets(specs: &[String], validate: bool, dns: Option<&DnsClient>) -> Result<TargetSet, ScanError> {
+    // Every spec may hold several comma separated targets.
+    // With validate set, every target must be scannable, e.g. IPv6 networks must be small enough.
+    // Hostnames are resolved with the built-in DNS client if there is one
+    let mut targets = TargetSet::new();
+    for spec in specs.iter().flat_map(|spec| spec.split(',')) {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let ranges = match parse_target(spec)? {
+            Some(ranges) => ranges,
+            None => resolve_hostname(spec, dns).await?,
+        };
+        if validate {
+            for (start, end) in ranges.iter() {
+                validate_range(*start, *end).map_err(|e| match e {
+                    // Name the target as given rather than the resulting range
+                    ScanError::InvalidTarget { reason, .. } => ScanError::InvalidTarget { target: spec.to_string(), reason },
+                    e => e,
+                })?;
+            }
+        }
+        targets.extend(ranges);
+    }
+    Ok(targets)
+}
+
+pub async fn resolve_targets(includes: &[String], excludes: &[String], dns: Option<&DnsClient>) -> Result<TargetSet, ScanError> {
+    // Merge all targets into one deduplicated set and remove the excluded addresses
+    let mut targets = parse_targets(includes, true, dns).await?;
+    let excluded = parse_targets(excludes, false, dns).await?;
+    for (start, end) in excluded.ranges() {
+        targets.remove_range(*start, *end);
+    }
+    Ok(targets)
+}
+
+pub fn read_target_file(path: &Path) -> Result<Vec<String>, ScanError> {
+    // One target per line, '-' reads from stdin. Empty lines and # comments are skipped
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    let mut targets: Vec<String> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if !line.is_empty() {
+            targets.push(line.to_string());
+        }
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specs(specs: &[&str]) -> Vec<String> {
+        specs.iter().map(|spec| spec.to_string()).collect()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn parse_targets_accepts_ipv6_addresses_and_small_networks() {
+        let targets = parse_targets(&specs(&["fd00::1", "fd00:1::/120"]), true, None).await.unwrap();
+        assert_eq!(targets.ranges(), &[(ip("fd00::1"), ip("fd00::1")), (ip("fd00:1::"), ip("fd00:1::ff"))]);
+        assert_eq!(targets.len(), 257);
+    }
+
+    #[tokio::test]
+    async fn parse_targets_rejects_large_ipv6_networks() {
+        let result = parse_targets(&specs(&["fd00::/64"]), true, None).await;
+        assert!(matches!(result, Err(ScanError::InvalidTarget { target, .. }) if target == "fd00::/64"));
+        // Exclusions are not scanned, they may be as large as they like
+        assert!(parse_targets(&specs(&["fd00::/64"]), false, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn parse_targets_keeps_the_address_families_apart() {
+        let targets = parse_targets(&specs(&["::1", "127.0.0.1", "::2"]), true, None).await.unwrap();
+        assert_eq!(targets.iter().collect::<Vec<IpAddr>>(), vec![ip("127.0.0.1"), ip("::1"), ip("::2")]);
+        assert!(targets.contains(ip("::2")));
+        assert!(!targets.contains(ip("0.0.0.2")));
+    }
+
+    #[tokio::test]
+    async fn parse_targets_accepts_ipv6_dash_ranges() {
+        let targets = parse_targets(&specs(&["fd00::10-fd00::1f"]), true, None).await.unwrap();
+        assert_eq!(targets.len(), 16);
+        assert!(parse_targets(&specs(&["10.0.0.1-fd00::1"]), true, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn parse_targets_returns_errors_instead_of_panicking() {
+        for spec in ["10.0.0.300", "10.0.0.5-10.0.0.1", "10.0.0-1.256", "10.0.3-1.1", "not_a_host!"] {
+            assert!(parse_targets(&specs(&[spec]), true, None).await.is_err(), "{:?} was accepted", spec);
+        }
+        assert!(matches!(parse_targets(&specs(&["10.0.0.5-10.0.0.1"]), true, None).await, Err(ScanError::InvertedRange { .. })));
+    }
+
+    #[tokio::test]
+    async fn parse_targets_skips_network_and_broadcast_addresses() {
+        let targets = parse_targets(&specs(&["192.168.0.0/24"]), true, None).await.unwrap();
+        assert_eq!(targets.ranges(), &[(ip("192.168.0.1"), ip("192.168.0.254"))]);
+        let targets = parse_targets(&specs(&["10.0.0.0/31", "10.0.0.7/32"]), true, None).await.unwrap();
+        assert_eq!(targets.ranges(), &[(ip("10.0.0.0"), ip("10.0.0.1")), (ip("10.0.0.7"), ip("10.0.0.7"))]);
+    }
+
+    #[tokio::test]
+    async fn parse_targets_handles_the_whole_address_space_without_listing_it() {
+        let targets = parse_targets(&specs(&["0.0.0.0/0"]), true, None).await.unwrap();
+        assert_eq!(targets.ranges(), &[(ip("0.0.0.1"), ip("255.255.255.254"))]);
+        assert_eq!(targets.len(), (1 << 32) - 2);
+        let excluded = parse_targets(&specs(&["::/0"]), false, None).await.unwrap();
+        assert_eq!(excluded.len(), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn parse_targets_expands_octet_ranges() {
+        let targets = parse_targets(&specs(&["10.0.1-2.1-3"]), true, None).await.unwrap();
+        assert_eq!(targets.ranges(), &[(ip("10.0.1.1"), ip("10.0.1.3")), (ip("10.0.2.1"), ip("10.0.2.3"))]);
+    }
+
+    #[tokio::test]
+    async fn resolve_targets_removes_the_exclusions() {
+        let targets = resolve_targets(&specs(&["10.0.0.0/24", "10.0.0.10"]), &specs(&["10.0.0.10-10.0.0.19", "10.0.0.254"]), None).await.unwrap();
+        assert_eq!(targets.ranges(), &[(ip("10.0.0.1"), ip("10.0.0.9")), (ip("10.0.0.20"), ip("10.0.0.253"))]);
+        assert_eq!(targets.len(), 243);
+        assert!(!targets.contains(ip("10.0.0.15")));
+    }
+
+    #[test]
+    fn target_set_merges_overlapping_and_adjacent_ranges() {
+        let mut targets = TargetSet::new();
+        targets.insert_range(ip("10.0.0.10"), ip("10.0.0.20"));
+        targets.insert_range(ip("10.0.0.21"), ip("10.0.0.30"));
+        targets.insert_range(ip("10.0.0.5"), ip("10.0.0.12"));
+        targets.insert_range(ip("fd00::1"), ip("fd00::1"));
+        assert_eq!(targets.ranges(), &[(ip("10.0.0.5"), ip("10.0.0.30")), (ip("fd00::1"), ip("fd00::1"))]);
+        assert_eq!(targets.iter().count(), 27);
+    }
+
+    #[test]
+    fn read_target_file_skips_comments_and_empty_lines() {
+        let path = std::env::temp_dir().join(format!("network_scanner_targets_{}.txt", std::process::id()));
+        std::fs::write(&path, "# office\n192.168.0.1\n\n  10.0.0.0/24  # lab\nprinter.lan\n").unwrap();
+        let targets = read_target_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(targets.unwrap(), vec!["192.168.0.1", "10.0.0.0/24", "printer.lan"]);
+    }
+}
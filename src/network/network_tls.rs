@@ -0,0 +1,715 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use std::sync::{Arc, OnceLock};
+
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use rand::random;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use x509_parser::oid_registry::{OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_NIST_EC_P521, OID_PKCS1_RSAENCRYPTION, OID_SIG_ED25519, OID_SIG_ED448};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate, X509Name};
+use x509_parser::public_key::PublicKey;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+// Ports of protocols that are wrapped in TLS right from the start
+pub const TLS_PORTS: [u16; 12] = [261, 443, 465, 563, 636, 853, 989, 990, 992, 993, 995, 8443];
+// Certificate chains are rarely larger than a few kilobytes, stop reading at some point
+const MAX_HANDSHAKE_LEN: usize = 65536;
+// Upper bound of simultaneously inspected ports per host
+const MAX_HANDSHAKES_IN_FLIGHT: usize = 16;
+
+const CONTENT_ALERT: u8 = 0x15;
+const CONTENT_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+const HANDSHAKE_CERTIFICATE: u8 = 11;
+const HANDSHAKE_SERVER_HELLO_DONE: u8 = 14;
+const EXTENSION_SERVER_NAME: u16 = 0x0000;
+const EXTENSION_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXTENSION_KEY_SHARE: u16 = 0x0033;
+const TLS_1_2: u16 = 0x0303;
+const TLS_1_3: u16 = 0x0304;
+
+// DER encoded object identifier of the emailAddress attribute, without tag and length
+const OID_EMAIL: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01];
+
+// Outcome of a TLS handshake with an open port
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TlsInfo {
+    // Highest protocol version the server agreed to, e.g. "TLSv1.3"
+    pub protocol: String,
+    // IANA name of the cipher suite the server picked, e.g. "TLS_AES_256_GCM_SHA384"
+    pub cipher: String,
+    // Server certificate, None if the server sent none or it couldn't be parsed
+    pub certificate: Option<CertificateInfo>,
+}
+
+// The parts of the server certificate that matter for an inventory
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CertificateInfo {
+    // Distinguished names like "CN=example.com, O=Example"
+    pub subject: String,
+    pub issuer: String,
+    // DNS names, IP addresses and email addresses of the subjectAltName extension
+    pub subject_alt_names: Vec<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    // "RSA", "EC", "Ed25519" or "Ed448"
+    pub key_type: String,
+    pub key_bits: Option<u32>,
+    // Issued by itself, i.e. subject and issuer are the same. The signature is not verified
+    pub self_signed: bool,
+}
+
+impl CertificateInfo {
+    pub fn is_expired(&self, at: DateTime<Utc>) -> bool {
+        at > self.not_after
+    }
+
+    pub fn is_not_yet_valid(&self, at: DateTime<Utc>) -> bool {
+        at < self.not_before
+    }
+}
+
+// Inspect the TLS ports of a host, ports that don't speak TLS are left out.
+// Every handshake needs one of the permits shared by all hosts of the scan
+pub async fn inspect_tls_ports(ip: IpAddr, ports: &[u16], server_name: Option<&str>, timeout: Duration, permits: &Semaphore) -> Vec<(u16, TlsInfo)> {
+    let mut infos: Vec<(u16, TlsInfo)> = stream::iter(ports.iter().copied())
+        .map(|port| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            inspect_tls(ip, port, server_name, timeout).await.map(|info| (port, info))
+        })
+        .buffer_unordered(MAX_HANDSHAKES_IN_FLIGHT)
+        .filter_map(|info| async move { info })
+        .collect()
+        .await;
+    infos.sort_unstable_by_key(|(port, _)| *port);
+    infos
+}
+
+// Complete a TLS handshake to learn the protocol version, the cipher and the certificate.
+// rustls only speaks TLS 1.2 and 1.3 with forward secrecy, so servers it can't agree with get the
+// hand made ClientHello, which offers the older ciphers as well. None if the port doesn't speak TLS
+pub async fn inspect_tls(ip: IpAddr, port: u16, server_name: Option<&str>, timeout: Duration) -> Option<TlsInfo> {
+    let address = SocketAddr::new(ip, port);
+    if let Some(stream) = connect_tls(address, server_name, timeout).await {
+        let (_, connection) = stream.get_ref();
+        return Some(TlsInfo {
+            protocol: connection.protocol_version().map(|version| protocol_name(version.into())).unwrap_or_default(),
+            cipher: connection.negotiated_cipher_suite().map(|suite| cipher_name(suite.suite().into())).unwrap_or_default(),
+            certificate: connection.peer_certificates().and_then(|chain| chain.first()).and_then(|der| parse_certificate(der)),
+        });
+    }
+    handshake(address, &client_hello(false, server_name), timeout).await.map(|hello| hello.to_info())
+}
+
+// TLS connection that accepts any certificate, like `curl --insecure`. SNI is only sent for hostnames
+pub async fn connect_tls(address: SocketAddr, server_name: Option<&str>, timeout: Duration) -> Option<TlsStream<TcpStream>> {
+    let name = server_name
+        .and_then(|name| ServerName::try_from(name.to_string()).ok())
+        .unwrap_or_else(|| ServerName::IpAddress(address.ip().into()));
+    let stream = tokio::time::timeout(timeout, TcpStream::connect(address)).await.ok()?.ok()?;
+    tokio::time::timeout(timeout, connector().connect(name, stream)).await.ok()?.ok()
+}
+
+fn connector() -> &'static TlsConnector {
+    static CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+    CONNECTOR.get_or_init(|| {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports TLS 1.2 and 1.3")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    })
+}
+
+// The scanner reports certificates instead of judging them, expired, self-signed and mismatched ones included.
+// The handshake signatures are still checked, so the server really holds the key of the certificate it sent
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(&self, _: &CertificateDer, _: &[CertificateDer], _: &ServerName, _: &[u8], _: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], certificate: &CertificateDer, signature: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, certificate, signature, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], certificate: &CertificateDer, signature: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, certificate, signature, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// What a server sent in reply to our ClientHello
+struct ServerHello {
+    version: u16,
+    cipher: u16,
+    certificate: Option<CertificateInfo>,
+}
+
+impl ServerHello {
+    fn to_info(&self) -> TlsInfo {
+        TlsInfo {
+            protocol: protocol_name(self.version),
+            cipher: cipher_name(self.cipher),
+            certificate: self.certificate.clone(),
+        }
+    }
+}
+
+async fn handshake(address: SocketAddr, hello: &[u8], timeout: Duration) -> Option<ServerHello> {
+    let mut stream = tokio::time::timeout(timeout, TcpStream::connect(address)).await.ok()?.ok()?;
+    stream.write_all(hello).await.ok()?;
+
+    // Handshake messages may be split over several records and records may hold several messages,
+    // so collect the payload of the handshake records and parse the messages as they complete
+    let mut messages: Vec<u8> = Vec::new();
+    let mut server_hello: Option<ServerHello> = None;
+    loop {
+        let Some((content_type, fragment)) = read_record(&mut stream, timeout).await else {
+            return server_hello;
+        };
+        if content_type != CONTENT_HANDSHAKE || messages.len() + fragment.len() > MAX_HANDSHAKE_LEN {
+            // An alert, or after a TLS 1.3 ServerHello the encrypted rest of the handshake
+            return server_hello;
+        }
+        messages.extend(fragment);
+
+        while messages.len() >= 4 {
+            let len = u32::from_be_bytes([0, messages[1], messages[2], messages[3]]) as usize;
+            if messages.len() < 4 + len {
+                break;
+            }
+            let body: Vec<u8> = messages[4..4 + len].to_vec();
+            let kind = messages[0];
+            messages.drain(..4 + len);
+
+            match kind {
+                HANDSHAKE_SERVER_HELLO => {
+                    let hello = parse_server_hello(&body)?;
+                    // Nothing readable follows a TLS 1.3 ServerHello
+                    if hello.version >= TLS_1_3 {
+                        return Some(hello);
+                    }
+                    server_hello = Some(hello);
+                },
+                HANDSHAKE_CERTIFICATE => {
+                    let mut hello = server_hello?;
+                    hello.certificate = parse_certificate_message(&body);
+                    return Some(hello);
+                },
+                HANDSHAKE_SERVER_HELLO_DONE => return server_hello,
+                _ => {},
+            }
+        }
+    }
+}
+
+async fn read_record(stream: &mut TcpStream, timeout: Duration) -> Option<(u8, Vec<u8>)> {
+    let mut header = [0u8; 5];
+    tokio::time::timeout(timeout, stream.read_exact(&mut header)).await.ok()?.ok()?;
+    if !matches!(header[0], CONTENT_ALERT | CONTENT_HANDSHAKE) || header[1] != 0x03 {
+        return None;
+    }
+    let mut fragment = vec![0u8; u16::from_be_bytes([header[3], header[4]]) as usize];
+    tokio::time::timeout(timeout, stream.read_exact(&mut fragment)).await.ok()?.ok()?;
+    Some((header[0], fragment))
+}
+
+fn parse_server_hello(body: &[u8]) -> Option<ServerHello> {
+    let mut reader = Reader::new(body);
+    let mut version = reader.u16()?;
+    reader.skip(32)?;
+    let session_id_len = reader.u8()? as usize;
+    reader.skip(session_id_len)?;
+    let cipher = reader.u16()?;
+    reader.skip(1)?;
+
+    // TLS 1.3 keeps 1.2 in the legacy field and puts the real version into an extension
+    if let Some(extensions_len) = reader.u16() {
+        let mut extensions = Reader::new(reader.take(extensions_len as usize)?);
+        while let (Some(kind), Some(len)) = (extensions.u16(), extensions.u16()) {
+            let data = extensions.take(len as usize)?;
+            if kind == EXTENSION_SUPPORTED_VERSIONS && data.len() == 2 {
+                version = u16::from_be_bytes([data[0], data[1]]);
+            }
+        }
+    }
+    Some(ServerHello { version, cipher, certificate: None })
+}
+
+// TLS 1.2 Certificate message: a 24 bit length of the chain, then every certificate with a 24 bit length. The first one is the server's
+fn parse_certificate_message(body: &[u8]) -> Option<CertificateInfo> {
+    let mut reader = Reader::new(body);
+    reader.skip(3)?;
+    let len = reader.u24()? as usize;
+    parse_certificate(reader.take(len)?)
+}
+
+// The parts of an X.509 certificate (RFC 5280) that are shown in the report
+fn parse_certificate(der: &[u8]) -> Option<CertificateInfo> {
+    let (_, certificate) = X509Certificate::from_der(der).ok()?;
+    let validity = certificate.validity();
+    let subject_alt_names = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension.value.general_names.iter().filter_map(format_general_name).collect(),
+        _ => Vec::new(),
+    };
+    let (key_type, key_bits) = public_key(certificate.public_key());
+
+    Some(CertificateInfo {
+        subject: format_name(certificate.subject()),
+        issuer: format_name(certificate.issuer()),
+        subject_alt_names,
+        not_before: DateTime::from_timestamp(validity.not_before.timestamp(), 0)?,
+        not_after: DateTime::from_timestamp(validity.not_after.timestamp(), 0)?,
+        key_type,
+        key_bits,
+        self_signed: certificate.subject().as_raw() == certificate.issuer().as_raw(),
+    })
+}
+
+// DNS names, email addresses and IP addresses, the other kinds of names hardly appear in server certificates
+fn format_general_name(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) | GeneralName::RFC822Name(name) => Some(name.to_string()),
+        GeneralName::IPAddress(address) => match address.len() {
+            4 => <[u8; 4]>::try_from(*address).ok().map(|address| Ipv4Addr::from(address).to_string()),
+            16 => <[u8; 16]>::try_from(*address).ok().map(|address| Ipv6Addr::from(address).to_string()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Key type and size. The curve tells the size of an EC key, the size of an RSA key is the bit length of its modulus
+fn public_key(info: &SubjectPublicKeyInfo) -> (String, Option<u32>) {
+    let algorithm = &info.algorithm.algorithm;
+    if *algorithm == OID_PKCS1_RSAENCRYPTION {
+        let bits = match info.parsed() {
+            Ok(PublicKey::RSA(key)) => key.modulus.iter().position(|byte| *byte != 0).map(|start| {
+                let modulus = &key.modulus[start..];
+                modulus.len() as u32 * 8 - modulus[0].leading_zeros()
+            }),
+            _ => None,
+        };
+        (String::from("RSA"), bits)
+    } else if *algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        let curve = info.algorithm.parameters.as_ref().and_then(|parameters| parameters.as_oid().ok());
+        let bits = match curve {
+            Some(curve) if curve == OID_EC_P256 => Some(256),
+            Some(curve) if curve == OID_NIST_EC_P384 => Some(384),
+            Some(curve) if curve == OID_NIST_EC_P521 => Some(521),
+            _ => None,
+        };
+        (String::from("EC"), bits)
+    } else if *algorithm == OID_SIG_ED25519 {
+        (String::from("Ed25519"), Some(256))
+    } else if *algorithm == OID_SIG_ED448 {
+        (String::from("Ed448"), Some(456))
+    } else {
+        (String::from("unknown"), None)
+    }
+}
+
+// Distinguished name in the order of the certificate, e.g. "C=US, O=Let's Encrypt, CN=R3"
+fn format_name(name: &X509Name) -> String {
+    let parts: Vec<String> = name.iter_attributes()
+        .map(|attribute| {
+            let key = match attribute.attr_type().as_bytes() {
+                [0x55, 0x04, 0x03] => String::from("CN"),
+                [0x55, 0x04, 0x06] => String::from("C"),
+                [0x55, 0x04, 0x07] => String::from("L"),
+                [0x55, 0x04, 0x08] => String::from("ST"),
+                [0x55, 0x04, 0x0a] => String::from("O"),
+                [0x55, 0x04, 0x0b] => String::from("OU"),
+                OID_EMAIL => String::from("emailAddress"),
+                oid => format_oid(oid),
+            };
+            let value = match attribute.as_str() {
+                Ok(value) => value.to_string(),
+                Err(_) => String::from_utf8_lossy(attribute.attr_value().data).to_string(),
+            };
+            format!("{}={}", key, value)
+        })
+        .collect();
+    parts.join(", ")
+}
+
+// Dotted form of an object identifier, for the attributes without a short name.
+// The first sub-identifier packs the first two arcs as 40 * first + second, where the second arc of 2.x
+// is unbounded, so the sub-identifier may span several bytes and any value from 80 on belongs to 2.x
+fn format_oid(oid: &[u8]) -> String {
+    let mut sub_identifiers: Vec<u64> = Vec::new();
+    let mut value: u64 = 0;
+    for byte in oid {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            sub_identifiers.push(value);
+            value = 0;
+        }
+    }
+    let Some((first, rest)) = sub_identifiers.split_first() else {
+        return String::new();
+    };
+    let (arc1, arc2) = match first {
+        0..=39 => (0, *first),
+        40..=79 => (1, first - 40),
+        _ => (2, first - 80),
+    };
+    let arcs: Vec<String> = [arc1, arc2].iter().chain(rest).map(|arc| arc.to_string()).collect();
+    arcs.join(".")
+}
+
+// Reads the big endian integers of the TLS wire format
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let value = self.data.get(..len)?;
+        self.data = &self.data[len..];
+        Some(value)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|value| value[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|value| u16::from_be_bytes([value[0], value[1]]))
+    }
+
+    fn u24(&mut self) -> Option<u32> {
+        self.take(3).map(|value| u32::from_be_bytes([0, value[0], value[1], value[2]]))
+    }
+}
+
+fn protocol_name(version: u16) -> String {
+    match version {
+        0x0300 => String::from("SSLv3"),
+        0x0301 => String::from("TLSv1.0"),
+        0x0302 => String::from("TLSv1.1"),
+        TLS_1_2 => String::from("TLSv1.2"),
+        TLS_1_3 => String::from("TLSv1.3"),
+        _ => format!("0x{:04x}", version),
+    }
+}
+
+fn cipher_name(cipher: u16) -> String {
+    let name = match cipher {
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        0x000a => "TLS_RSA_WITH_3DES_EDE_CBC_SHA",
+        _ => return format!("0x{:04x}", cipher),
+    };
+    name.to_string()
+}
+
+// ClientHello with the cipher suites and extensions of a current browser, minus the ones that need state.
+// Any TLS server answers it with a ServerHello or at least an alert. With `tls13` it also offers TLS 1.3 with
+// a random X25519 key share, which is fine as we never get to use the keys
+pub fn client_hello(tls13: bool, server_name: Option<&str>) -> Vec<u8> {
+    const TLS13_CIPHER_SUITES: [u16; 3] = [0x1301, 0x1302, 0x1303];
+    const CIPHER_SUITES: [u16; 13] = [0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035, 0x000a];
+    const GROUPS: [u16; 3] = [0x001d, 0x0017, 0x0018];
+    const SIGNATURE_ALGORITHMS: [u16; 10] = [0x0403, 0x0503, 0x0603, 0x0804, 0x0805, 0x0806, 0x0401, 0x0501, 0x0601, 0x0201];
+
+    let u16_list = |values: &[u16]| -> Vec<u8> {
+        let mut list = ((values.len() * 2) as u16).to_be_bytes().to_vec();
+        list.extend(values.iter().flat_map(|value| value.to_be_bytes()));
+        list
+    };
+    let extension = |kind: u16, data: &[u8]| -> Vec<u8> {
+        let mut extension = kind.to_be_bytes().to_vec();
+        extension.extend((data.len() as u16).to_be_bytes());
+        extension.extend(data);
+        extension
+    };
+
+    let mut extensions = Vec::new();
+    // SNI only takes hostnames, no addresses
+    if let Some(name) = server_name.filter(|name| name.parse::<IpAddr>().is_err()) {
+        let mut entry = vec![0];
+        entry.extend((name.len() as u16).to_be_bytes());
+        entry.extend(name.as_bytes());
+        let mut list = (entry.len() as u16).to_be_bytes().to_vec();
+        list.extend(entry);
+        extensions.extend(extension(EXTENSION_SERVER_NAME, &list));
+    }
+    extensions.extend(extension(0x000a, &u16_list(&GROUPS)));
+    extensions.extend(extension(0x000b, &[1, 0]));
+    extensions.extend(extension(0x000d, &u16_list(&SIGNATURE_ALGORITHMS)));
+    extensions.extend(extension(0xff01, &[0]));
+    if tls13 {
+        extensions.extend(extension(EXTENSION_SUPPORTED_VERSIONS, &[4, 0x03, 0x04, 0x03, 0x03]));
+        let mut share = 0x001du16.to_be_bytes().to_vec();
+        share.extend(32u16.to_be_bytes());
+        share.extend(random::<[u8; 32]>());
+        let mut shares = (share.len() as u16).to_be_bytes().to_vec();
+        shares.extend(share);
+        extensions.extend(extension(EXTENSION_KEY_SHARE, &shares));
+    }
+
+    let mut cipher_suites: Vec<u16> = Vec::new();
+    if tls13 {
+        cipher_suites.extend(TLS13_CIPHER_SUITES);
+    }
+    cipher_suites.extend(CIPHER_SUITES);
+
+    let mut hello = TLS_1_2.to_be_bytes().to_vec();
+    hello.extend(random::<[u8; 32]>());
+    // TLS 1.3 middlebox compatibility wants a session id
+    if tls13 {
+        hello.push(32);
+        hello.extend(random::<[u8; 32]>());
+    } else {
+        hello.push(0);
+    }
+    hello.extend(u16_list(&cipher_suites));
+    hello.extend([1, 0]);
+    hello.extend((extensions.len() as u16).to_be_bytes());
+    hello.extend(extensions);
+
+    // Handshake header with a 24 bit length, then the record header
+    let mut handshake = vec![0x01];
+    handshake.extend(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend(hello);
+    let mut record = vec![CONTENT_HANDSHAKE, 0x03, 0x01];
+    record.extend((handshake.len() as u16).to_be_bytes());
+    record.extend(handshake);
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::{ServerConfig, SupportedProtocolVersion};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    // Self-signed RSA 2048 certificate for example.com with an attribute of the unnamed arc 2.999
+    const RSA_CERTIFICATE: &[u8] = include_bytes!("../../data/test/rsa.der");
+    const RSA_KEY: &[u8] = include_bytes!("../../data/test/rsa.pk8");
+    // P-384 certificate for mail.example.com issued by the RSA certificate, valid during 2020
+    const EC_CERTIFICATE: &[u8] = include_bytes!("../../data/test/ec.der");
+    const EC_KEY: &[u8] = include_bytes!("../../data/test/ec.pk8");
+    const ED25519_CERTIFICATE: &[u8] = include_bytes!("../../data/test/ed25519.der");
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn utc(year: i32, month: u32, day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, 0, 0, 0).unwrap()
+    }
+
+    // Stand-in TLS server limited to the given protocol versions
+    async fn tls_server(certificate: &[u8], key: &[u8], versions: &[&'static SupportedProtocolVersion]) -> u16 {
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(versions)
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![CertificateDer::from(certificate.to_vec())], PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.to_vec())))
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        let _ = stream.read(&mut [0u8; 16]).await;
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    // Stand-in server that answers every connection with the same bytes, whatever the client sent
+    async fn canned_server(reply: Vec<u8>) -> u16 {
+        let listener = TcpListener::bind((LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let reply = reply.clone();
+                tokio::spawn(async move {
+                    let _ = stream.read(&mut [0u8; 4096]).await;
+                    let _ = stream.write_all(&reply).await;
+                    let _ = stream.read(&mut [0u8; 4096]).await;
+                });
+            }
+        });
+        port
+    }
+
+    fn handshake_record(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![kind];
+        message.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        message.extend(body);
+        let mut record = vec![CONTENT_HANDSHAKE, 0x03, 0x01];
+        record.extend((message.len() as u16).to_be_bytes());
+        record.extend(message);
+        record
+    }
+
+    // TLS 1.0 ServerHello with TLS_RSA_WITH_AES_128_CBC_SHA, the certificate and ServerHelloDone, as sent by an old server
+    fn legacy_server_reply() -> Vec<u8> {
+        let mut hello = vec![0x03, 0x01];
+        hello.extend([0u8; 32]);
+        hello.extend([0, 0x00, 0x2f, 0]);
+        let mut chain = (RSA_CERTIFICATE.len() as u32).to_be_bytes()[1..].to_vec();
+        chain.extend(RSA_CERTIFICATE);
+        let mut certificate = (chain.len() as u32).to_be_bytes()[1..].to_vec();
+        certificate.extend(chain);
+
+        let mut reply = handshake_record(HANDSHAKE_SERVER_HELLO, &hello);
+        reply.extend(handshake_record(HANDSHAKE_CERTIFICATE, &certificate));
+        reply.extend(handshake_record(HANDSHAKE_SERVER_HELLO_DONE, &[]));
+        reply
+    }
+
+    #[test]
+    fn format_oid_decodes_the_first_two_arcs() {
+        assert_eq!(format_oid(&[0x55, 0x04, 0x03]), "2.5.4.3");
+        assert_eq!(format_oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d]), "1.2.840.113549");
+        assert_eq!(format_oid(&[0x00]), "0.0");
+        assert_eq!(format_oid(&[0x51, 0x01]), "2.1.1");
+        assert_eq!(format_oid(&[0x88, 0x37, 0x01]), "2.999.1");
+        assert_eq!(format_oid(&[]), "");
+    }
+
+    #[test]
+    fn parse_rsa_certificate() {
+        let certificate = parse_certificate(RSA_CERTIFICATE).unwrap();
+        assert_eq!(certificate.subject, "C=DE, O=Example, CN=example.com, 2.999.1=test");
+        assert_eq!(certificate.issuer, certificate.subject);
+        assert_eq!(certificate.subject_alt_names, ["example.com", "www.example.com", "192.0.2.10", "2001:db8::10", "admin@example.com"]);
+        assert_eq!((certificate.not_before, certificate.not_after), (utc(2024, 1, 1), utc(2034, 1, 1)));
+        assert_eq!((certificate.key_type.as_str(), certificate.key_bits), ("RSA", Some(2048)));
+        assert!(certificate.self_signed);
+    }
+
+    #[test]
+    fn parse_ec_certificate_issued_by_another() {
+        let certificate = parse_certificate(EC_CERTIFICATE).unwrap();
+        assert_eq!(certificate.subject, "CN=mail.example.com");
+        assert_eq!(certificate.issuer, "C=DE, O=Example, CN=example.com, 2.999.1=test");
+        assert_eq!(certificate.subject_alt_names, ["mail.example.com"]);
+        assert_eq!((certificate.key_type.as_str(), certificate.key_bits), ("EC", Some(384)));
+        assert!(!certificate.self_signed);
+
+        assert!(certificate.is_expired(utc(2021, 1, 2)));
+        assert!(certificate.is_not_yet_valid(utc(2019, 12, 31)));
+        assert!(!certificate.is_expired(utc(2020, 6, 1)) && !certificate.is_not_yet_valid(utc(2020, 6, 1)));
+    }
+
+    #[test]
+    fn parse_ed25519_certificate() {
+        let certificate = parse_certificate(ED25519_CERTIFICATE).unwrap();
+        assert_eq!(certificate.subject, "CN=ed25519.example.com");
+        assert_eq!(certificate.subject_alt_names, Vec::<String>::new());
+        assert_eq!((certificate.key_type.as_str(), certificate.key_bits), ("Ed25519", Some(256)));
+    }
+
+    #[test]
+    fn parse_certificate_rejects_garbage() {
+        assert_eq!(parse_certificate(&RSA_CERTIFICATE[..100]), None);
+        assert_eq!(parse_certificate(b"not a certificate"), None);
+        assert_eq!(parse_certificate_message(&[0, 0, 10, 0, 0, 20]), None);
+    }
+
+    #[test]
+    fn parse_server_hello_prefers_the_supported_versions_extension() {
+        let mut body = TLS_1_2.to_be_bytes().to_vec();
+        body.extend([0u8; 32]);
+        body.extend([0, 0x13, 0x02, 0]);
+        body.extend([0, 6, 0x00, 0x2b, 0, 2, 0x03, 0x04]);
+        let hello = parse_server_hello(&body).unwrap();
+        assert_eq!((hello.version, hello.cipher), (TLS_1_3, 0x1302));
+        assert_eq!(hello.to_info().protocol, "TLSv1.3");
+        assert_eq!(hello.to_info().cipher, "TLS_AES_256_GCM_SHA384");
+
+        assert!(parse_server_hello(&body[..20]).is_none());
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_reads_the_certificate_of_a_tls13_only_server() {
+        let port = tls_server(RSA_CERTIFICATE, RSA_KEY, &[&rustls::version::TLS13]).await;
+        let info = inspect_tls(LOCALHOST, port, Some("example.com"), TEST_TIMEOUT).await.unwrap();
+        assert_eq!(info.protocol, "TLSv1.3");
+        assert!(info.cipher.starts_with("TLS_AES_") || info.cipher == "TLS_CHACHA20_POLY1305_SHA256", "{}", info.cipher);
+        assert_eq!(info.certificate, parse_certificate(RSA_CERTIFICATE));
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_of_a_tls12_server() {
+        let port = tls_server(EC_CERTIFICATE, EC_KEY, &[&rustls::version::TLS12]).await;
+        let info = inspect_tls(LOCALHOST, port, None, TEST_TIMEOUT).await.unwrap();
+        assert_eq!(info.protocol, "TLSv1.2");
+        assert!(info.cipher.starts_with("TLS_ECDHE_ECDSA_WITH_"), "{}", info.cipher);
+        assert_eq!(info.certificate.unwrap().subject, "CN=mail.example.com");
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_falls_back_to_the_hand_made_hello_for_old_servers() {
+        let port = canned_server(legacy_server_reply()).await;
+        let info = inspect_tls(LOCALHOST, port, None, TEST_TIMEOUT).await.unwrap();
+        assert_eq!(info.protocol, "TLSv1.0");
+        assert_eq!(info.cipher, "TLS_RSA_WITH_AES_128_CBC_SHA");
+        assert_eq!(info.certificate, parse_certificate(RSA_CERTIFICATE));
+    }
+
+    #[tokio::test]
+    async fn inspect_tls_ports_leave_out_other_protocols() {
+        let tls = tls_server(RSA_CERTIFICATE, RSA_KEY, &[&rustls::version::TLS13]).await;
+        let ssh = canned_server(b"SSH-2.0-OpenSSH_9.6\r\n".to_vec()).await;
+        let permits = Semaphore::new(2);
+        let infos = inspect_tls_ports(LOCALHOST, &[ssh, tls], None, Duration::from_millis(500), &permits).await;
+        assert_eq!(infos.iter().map(|(port, _)| *port).collect::<Vec<u16>>(), [tls]);
+    }
+}
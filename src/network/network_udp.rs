@@ -0,0 +1,241 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use rand::random;
+use tokio::net::UdpSocket;
+use tokio::sync::Semaphore;
+
+use crate::network::network_core::{PortInfo, PortState};
+use crate::network::network_services::ServiceInfo;
+
+// Ports scanned with --udp if the user does not select any
+pub const UDP_PORTS: [u16; 9] = [53, 67, 69, 123, 137, 161, 514, 1900, 5353];
+// Time to wait for a reply or an ICMP port unreachable after every datagram
+pub const DEFAULT_UDP_TIMEOUT: Duration = Duration::from_secs(1);
+// UDP is unreliable and ICMP errors are rate limited, so a port gets a second datagram before it counts as open|filtered
+const UDP_ATTEMPTS: usize = 2;
+// Upper bound of simultaneously probed UDP ports per host
+const MAX_UDP_PORTS_IN_FLIGHT: usize = 64;
+const MAX_DATAGRAM_LEN: usize = 2048;
+
+// Datagram that makes a server of the port's protocol answer, like nmap-payloads.
+// Unknown ports get an empty datagram, which only an ICMP port unreachable answers
+fn payload(port: u16) -> Vec<u8> {
+    match port {
+        // DNS query for the NS records of the root zone, mDNS answers the same
+        53 | 5353 => {
+            let mut query = random::<u16>().to_be_bytes().to_vec();
+            query.extend([0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01]);
+            query
+        },
+        // DHCPINFORM, servers answer to port 68 though, so DHCP is usually open|filtered
+        67 => {
+            let mut request = vec![0u8; 240];
+            request[0] = 1;
+            request[1] = 1;
+            request[2] = 6;
+            request[4..8].copy_from_slice(&random::<[u8; 4]>());
+            request[236..240].copy_from_slice(&[0x63, 0x82, 0x53, 0x63]);
+            request.extend([53, 1, 8, 255]);
+            request
+        },
+        // TFTP read request, an existing server answers with the file or an error
+        69 => b"\x00\x01network_scanner.txt\x00octet\x00".to_vec(),
+        // NTP v3 client request
+        123 => {
+            let mut request = vec![0u8; 48];
+            request[0] = 0x1b;
+            request
+        },
+        // NetBIOS node status request for the wildcard name
+        137 => {
+            let mut request = random::<u16>().to_be_bytes().to_vec();
+            request.extend([0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x43, 0x4b]);
+            request.extend([0x41; 30]);
+            request.extend([0x00, 0x00, 0x21, 0x00, 0x01]);
+            request
+        },
+        // SNMPv1 GetRequest for sysDescr with the community "public"
+        161 => vec![
+            0x30, 0x29, 0x02, 0x01, 0x00, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c',
+            0xa0, 0x1c, 0x02, 0x04, 0x00, 0x00, 0x00, 0x01, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00,
+            0x30, 0x0e, 0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+        ],
+        // SSDP discovery
+        1900 => b"M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: ssdp:all\r\n\r\n".to_vec(),
+        // Syslog never answers, an empty datagram at least doesn't end up in the log
+        _ => Vec::new(),
+    }
+}
+
+// nmap's name of the protocol behind the port, used for the ports that answered
+fn protocol_name(port: u16) -> &'static str {
+    match port {
+        53 => "domain",
+        67 => "dhcps",
+        69 => "tftp",
+        123 => "ntp",
+        137 => "netbios-ns",
+        161 => "snmp",
+        514 => "syslog",
+        1900 => "upnp",
+        5353 => "mdns",
+        _ => "unknown",
+    }
+}
+
+// Probe every port with its protocol's payload and classify it by the answer: a reply means open,
+// an ICMP port unreachable closed and silence open|filtered. Every probe needs one of the permits shared by all hosts of the scan
+pub async fn scan_ports_udp(ip: IpAddr, timeout: Duration, ports: &[u16], permits: &Semaphore) -> Vec<PortInfo> {
+    let mut results: Vec<PortInfo> = stream::iter(ports.iter().copied())
+        .map(|port| async move {
+            let Ok(_permit) = permits.acquire().await else {
+                return None;
+            };
+            Some(probe_udp(ip, port, timeout).await)
+        })
+        .buffer_unordered(MAX_UDP_PORTS_IN_FLIGHT)
+        .filter_map(|result| async move { result })
+        .collect()
+        .await;
+
+    // Completion order is arbitrary, report the ports sorted
+    results.sort_unstable_by_key(|info| info.port);
+    results
+}
+
+// A socket that can't be opened or a datagram that can't be sent or answered is an error of the port, so it's still reported
+async fn probe_udp(ip: IpAddr, port: u16, timeout: Duration) -> PortInfo {
+    let local: SocketAddr = match ip {
+        IpAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        IpAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    // A connected socket gets the ICMP errors of its peer reported as ECONNREFUSED, no raw socket needed
+    let socket = match UdpSocket::bind(local).await {
+        Ok(socket) => socket,
+        Err(e) => return udp_port(port, PortState::Error(e.kind().into()), None),
+    };
+    // Without a route to the host, or for a broadcast address, connect already fails
+    if let Err(e) = socket.connect(SocketAddr::new(ip, port)).await {
+        return udp_port(port, PortState::Error(e.kind().into()), None);
+    }
+    let payload = payload(port);
+
+    let mut reply = [0u8; MAX_DATAGRAM_LEN];
+    for _ in 0..UDP_ATTEMPTS {
+        match socket.send(&payload).await {
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => return udp_port(port, PortState::Closed, None),
+            Err(e) => return udp_port(port, PortState::Error(e.kind().into()), None),
+            Ok(_) => {},
+        }
+        match tokio::time::timeout(timeout, socket.recv(&mut reply)).await {
+            Ok(Ok(_)) => {
+                let service = ServiceInfo {
+                    name: protocol_name(port).to_string(),
+                    product: None,
+                    version: None,
+                    banner: None,
+                };
+                return udp_port(port, PortState::Open, Some(service));
+            },
+            Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => return udp_port(port, PortState::Closed, None),
+            // Other ICMP errors, e.g. host or network unreachable, are reported like the TCP scan does
+            Ok(Err(e)) => return udp_port(port, PortState::Error(e.kind().into()), None),
+            Err(_) => {},
+        }
+    }
+    udp_port(port, PortState::OpenFiltered, None)
+}
+
+fn udp_port(port: u16, state: PortState, service: Option<ServiceInfo>) -> PortInfo {
+    PortInfo { port, state, service, tls: None, http: None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TEST_TIMEOUT: Duration = Duration::from_millis(200);
+
+    // Stand-in server that echoes every datagram
+    async fn echo_server() -> u16 {
+        let socket = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut datagram = [0u8; MAX_DATAGRAM_LEN];
+            while let Ok((len, peer)) = socket.recv_from(&mut datagram).await {
+                let _ = socket.send_to(&datagram[..len], peer).await;
+            }
+        });
+        port
+    }
+
+    // Port that was just free, the kernel answers it with an ICMP port unreachable
+    async fn closed_port() -> u16 {
+        UdpSocket::bind((LOCALHOST, 0)).await.unwrap().local_addr().unwrap().port()
+    }
+
+    // Stand-in server that reads the datagrams and never answers
+    async fn silent_server() -> (UdpSocket, u16) {
+        let socket = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        (socket, port)
+    }
+
+    #[test]
+    fn payloads_fit_their_protocols() {
+        let dns = payload(53);
+        assert_eq!(dns.len(), 17);
+        assert_eq!(&dns[2..6], [0x01, 0x00, 0x00, 0x01]);
+        assert_eq!(payload(123).len(), 48);
+        assert_eq!(payload(123)[0], 0x1b);
+        assert_eq!(payload(137).len(), 50);
+        assert_eq!(payload(161)[1] as usize, payload(161).len() - 2);
+        assert!(payload(1900).starts_with(b"M-SEARCH"));
+        assert!(payload(514).is_empty());
+        assert_eq!(protocol_name(5353), "mdns");
+        assert_eq!(protocol_name(4000), "unknown");
+    }
+
+    #[tokio::test]
+    async fn probe_udp_tells_open_from_closed() {
+        let open = echo_server().await;
+        let info = probe_udp(LOCALHOST, open, TEST_TIMEOUT).await;
+        assert_eq!(info.state, PortState::Open);
+        assert_eq!(info.service.unwrap().name, "unknown");
+
+        let closed = closed_port().await;
+        assert_eq!(probe_udp(LOCALHOST, closed, TEST_TIMEOUT).await.state, PortState::Closed);
+    }
+
+    #[tokio::test]
+    async fn probe_udp_reports_silence_as_open_filtered() {
+        let (_socket, port) = silent_server().await;
+        assert_eq!(probe_udp(LOCALHOST, port, TEST_TIMEOUT).await.state, PortState::OpenFiltered);
+    }
+
+    #[tokio::test]
+    async fn probe_udp_keeps_ports_that_fail_to_send() {
+        // Datagrams to the broadcast address need SO_BROADCAST, the kernel refuses them otherwise
+        let info = probe_udp(IpAddr::V4(Ipv4Addr::BROADCAST), 9, TEST_TIMEOUT).await;
+        assert!(matches!(info.state, PortState::Error(_)), "{:?}", info.state);
+    }
+
+    #[tokio::test]
+    async fn scan_ports_udp_reports_every_port_sorted() {
+        let open = echo_server().await;
+        let closed = closed_port().await;
+        let (_socket, silent) = silent_server().await;
+        let permits = Semaphore::new(1);
+        let states: Vec<(u16, PortState)> = scan_ports_udp(LOCALHOST, TEST_TIMEOUT, &[silent, closed, open], &permits).await
+            .into_iter()
+            .map(|info| (info.port, info.state))
+            .collect();
+        let mut expected = vec![(open, PortState::Open), (closed, PortState::Closed), (silent, PortState::OpenFiltered)];
+        expected.sort_unstable_by_key(|(port, _)| *port);
+        assert_eq!(states, expected);
+    }
+}
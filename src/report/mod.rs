@@ -0,0 +1,96 @@
+pub mod report_csv;
+pub mod report_json;
+pub mod report_text;
+pub mod report_xml;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::{Serialize, Deserialize};
+
+use crate::network::network_core::{PortScanResult, Status};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    // Human readable report
+    Text,
+    // One JSON document with metadata and every host, written once the scan is done
+    Json,
+    // One JSON object per line and host, streamed as soon as a host is done
+    Ndjson,
+    // Spreadsheet friendly, one row per host or per host and open port
+    Csv,
+    // nmap compatible XML, as written by `nmap -oX`
+    Xml,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScanMetadata {
+    pub command: String,
+    pub targets: Vec<String>,
+    pub ports: String,
+    // UDP ports in the same notation, None without UDP scan
+    #[serde(default)]
+    pub udp_ports: Option<String>,
+    // TCP ports scanned with SYN packets instead of connects
+    #[serde(default)]
+    pub syn_scan: bool,
+    pub timeout_ms: u32,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScanReport {
+    pub metadata: ScanMetadata,
+    pub hosts: Vec<PortScanResult>,
+}
+
+impl ScanReport {
+    pub fn n_up(&self) -> u32 {
+        self.hosts.iter().filter(|result| result.status == Status::Up).count() as u32
+    }
+}
+
+pub fn open_output(path: Option<&Path>) -> io::Result<Box<dyn Write + Send>> {
+    // Write to the given file or stdout if no file is set
+    match path {
+        Some(path) => Ok(Box::new(BufWriter::new(File::create(path)?))),
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+// Small report with one host up and one down, shared by the tests of the report formats
+#[cfg(test)]
+pub(crate) fn sample_report() -> ScanReport {
+    use chrono::TimeZone;
+    use crate::network::network_core::{DiscoveryMethod, PortState, Reason};
+
+    let start_time = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+    let up = PortScanResult::new(
+        "192.168.0.10".parse().unwrap(),
+        Status::Up,
+        Reason::SynAck(22),
+        vec![DiscoveryMethod::TcpConnect],
+        vec![(22, PortState::Open), (80, PortState::Open), (443, PortState::Closed)],
+        None,
+    ).with_hostname(Some(String::from("nas.lan")), None);
+    let down = PortScanResult::new("192.168.0.11".parse().unwrap(), Status::Down, Reason::NoResponse, vec![DiscoveryMethod::TcpConnect], Vec::new(), None);
+
+    ScanReport {
+        metadata: ScanMetadata {
+            command: String::from("network_scanner 192.168.0.10-192.168.0.11 --ports 22,80,443"),
+            targets: vec![String::from("192.168.0.10-192.168.0.11")],
+            ports: String::from("22,80,443"),
+            udp_ports: None,
+            syn_scan: false,
+            timeout_ms: 1000,
+            start_time,
+            end_time: start_time + chrono::Duration::seconds(3),
+        },
+        hosts: vec![up, down],
+    }
+}
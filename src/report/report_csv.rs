@@ -0,0 +1,206 @@
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::network::network_core::{PingStats, PortInfo, PortState, Status};
+use crate::report::ScanReport;
+
+// Joins the open ports and discovery methods within a single cell, a comma would clash with the column separator
+const PORT_SEPARATOR: &str = ";";
+
+#[derive(Serialize)]
+struct HostRow<'a> {
+    ip: IpAddr,
+    status: &'a Status,
+    reason: &'static str,
+    reason_port: Option<u16>,
+    discovery: String,
+    mac_address: Option<&'a str>,
+    vendor: Option<&'a str>,
+    hostname: Option<&'a str>,
+    open_tcp_ports: String,
+    open_udp_ports: String,
+    // "port/name" of the identified services
+    services: String,
+    rtt_ms: Option<f64>,
+    rtt_min_ms: Option<f64>,
+    rtt_max_ms: Option<f64>,
+    rtt_stddev_ms: Option<f64>,
+    packet_loss: Option<f64>,
+    ttl: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct PortRow<'a> {
+    ip: IpAddr,
+    status: &'a Status,
+    reason: &'static str,
+    reason_port: Option<u16>,
+    discovery: String,
+    mac_address: Option<&'a str>,
+    vendor: Option<&'a str>,
+    hostname: Option<&'a str>,
+    protocol: Option<&'static str>,
+    port: Option<u16>,
+    state: Option<&'static str>,
+    service: Option<&'a str>,
+    product: Option<&'a str>,
+    version: Option<&'a str>,
+    tls_protocol: Option<&'a str>,
+    tls_cipher: Option<&'a str>,
+    cert_subject: Option<&'a str>,
+    cert_issuer: Option<&'a str>,
+    cert_subject_alt_names: Option<String>,
+    cert_not_before: Option<String>,
+    cert_not_after: Option<String>,
+    cert_key_type: Option<&'a str>,
+    cert_key_bits: Option<u32>,
+    cert_self_signed: Option<bool>,
+    http_status: Option<u16>,
+    http_server: Option<&'a str>,
+    http_title: Option<&'a str>,
+    http_location: Option<&'a str>,
+    favicon_hash: Option<i32>,
+    rtt_ms: Option<f64>,
+    rtt_min_ms: Option<f64>,
+    rtt_max_ms: Option<f64>,
+    rtt_stddev_ms: Option<f64>,
+    packet_loss: Option<f64>,
+    ttl: Option<u8>,
+}
+
+pub fn write_csv_report(out: &mut dyn Write, report: &ScanReport, per_port: bool) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+
+    for result in report.hosts.iter() {
+        // The csv crate can't flatten nested structs, so the ping statistics are copied column by column
+        let ping: Option<&PingStats> = result.ping.as_ref();
+        let discovery = result.discovery.iter().map(|method| format!("{:?}", method)).collect::<Vec<String>>().join(PORT_SEPARATOR);
+        if per_port {
+            // Hosts without ports still get a row with an empty port, so no host goes missing
+            let ports: Vec<Option<(&'static str, &PortInfo)>> = if result.tcp_ports.is_empty() && result.udp_ports.is_empty() {
+                vec![None]
+            } else {
+                result.tcp_ports.iter().map(|info| Some(("tcp", info)))
+                    .chain(result.udp_ports.iter().map(|info| Some(("udp", info))))
+                    .collect()
+            };
+            for port in ports {
+                let protocol = port.map(|(protocol, _)| protocol);
+                let info = port.map(|(_, info)| info);
+                let service = info.and_then(|info| info.service.as_ref());
+                let tls = info.and_then(|info| info.tls.as_ref());
+                let certificate = tls.and_then(|tls| tls.certificate.as_ref());
+                let http = info.and_then(|info| info.http.as_ref());
+                writer.serialize(PortRow {
+                    ip: result.ip_address,
+                    status: &result.status,
+                    reason: result.reason.name(),
+                    reason_port: result.reason.port(),
+                    discovery: discovery.clone(),
+                    mac_address: result.mac_address.as_deref(),
+                    vendor: result.vendor.as_deref(),
+                    hostname: result.hostname.as_deref(),
+                    protocol,
+                    port: info.map(|info| info.port),
+                    state: info.map(|info| info.state.name()),
+                    service: service.map(|service| service.name.as_str()),
+                    product: service.and_then(|service| service.product.as_deref()),
+                    version: service.and_then(|service| service.version.as_deref()),
+                    tls_protocol: tls.map(|tls| tls.protocol.as_str()),
+                    tls_cipher: tls.map(|tls| tls.cipher.as_str()),
+                    cert_subject: certificate.map(|certificate| certificate.subject.as_str()),
+                    cert_issuer: certificate.map(|certificate| certificate.issuer.as_str()),
+                    cert_subject_alt_names: certificate.map(|certificate| certificate.subject_alt_names.join(PORT_SEPARATOR)),
+                    cert_not_before: certificate.map(|certificate| certificate.not_before.to_rfc3339()),
+                    cert_not_after: certificate.map(|certificate| certificate.not_after.to_rfc3339()),
+                    cert_key_type: certificate.map(|certificate| certificate.key_type.as_str()),
+                    cert_key_bits: certificate.and_then(|certificate| certificate.key_bits),
+                    cert_self_signed: certificate.map(|certificate| certificate.self_signed),
+                    http_status: http.map(|http| http.status_code),
+                    http_server: http.and_then(|http| http.server.as_deref()),
+                    http_title: http.and_then(|http| http.title.as_deref()),
+                    http_location: http.and_then(|http| http.location.as_deref()),
+                    favicon_hash: http.and_then(|http| http.favicon_hash),
+                    rtt_ms: result.rtt_ms,
+                    rtt_min_ms: ping.and_then(|ping| ping.rtt_min_ms),
+                    rtt_max_ms: ping.and_then(|ping| ping.rtt_max_ms),
+                    rtt_stddev_ms: ping.and_then(|ping| ping.rtt_stddev_ms),
+                    packet_loss: ping.map(|ping| ping.packet_loss),
+                    ttl: ping.and_then(|ping| ping.ttl),
+                })?;
+            }
+        } else {
+            writer.serialize(HostRow {
+                ip: result.ip_address,
+                status: &result.status,
+                reason: result.reason.name(),
+                reason_port: result.reason.port(),
+                discovery,
+                mac_address: result.mac_address.as_deref(),
+                vendor: result.vendor.as_deref(),
+                hostname: result.hostname.as_deref(),
+                open_tcp_ports: result.open_tcp_ports.iter().map(|port| port.to_string()).collect::<Vec<String>>().join(PORT_SEPARATOR),
+                open_udp_ports: result.udp_ports.iter()
+                    .filter(|info| info.state == PortState::Open)
+                    .map(|info| info.port.to_string())
+                    .collect::<Vec<String>>()
+                    .join(PORT_SEPARATOR),
+                services: result.tcp_ports.iter()
+                    .filter_map(|info| info.service.as_ref().map(|service| format!("{}/{}", info.port, service.name)))
+                    .collect::<Vec<String>>()
+                    .join(PORT_SEPARATOR),
+                rtt_ms: result.rtt_ms,
+                rtt_min_ms: ping.and_then(|ping| ping.rtt_min_ms),
+                rtt_max_ms: ping.and_then(|ping| ping.rtt_max_ms),
+                rtt_stddev_ms: ping.and_then(|ping| ping.rtt_stddev_ms),
+                packet_loss: ping.map(|ping| ping.packet_loss),
+                ttl: ping.and_then(|ping| ping.ttl),
+            })?;
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::sample_report;
+
+    // Header and rows of the written CSV, every row as a map from column to cell
+    fn write_and_read(per_port: bool) -> Vec<std::collections::HashMap<String, String>> {
+        let mut out: Vec<u8> = Vec::new();
+        write_csv_report(&mut out, &sample_report(), per_port).unwrap();
+        csv::Reader::from_reader(out.as_slice()).deserialize().map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn csv_writes_one_row_per_host() {
+        let rows = write_and_read(false);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["ip"], "192.168.0.10");
+        assert_eq!(rows[0]["status"], "Up");
+        assert_eq!(rows[0]["hostname"], "nas.lan");
+        assert_eq!(rows[0]["reason"], "syn-ack");
+        assert_eq!(rows[0]["reason_port"], "22");
+        assert_eq!(rows[0]["open_tcp_ports"], "22;80");
+        assert_eq!(rows[1]["status"], "Down");
+        assert_eq!(rows[1]["hostname"], "");
+        assert_eq!(rows[1]["open_tcp_ports"], "");
+    }
+
+    #[test]
+    fn csv_per_port_writes_every_probed_port_and_keeps_hosts_without_ports() {
+        let rows = write_and_read(true);
+        let ports: Vec<(&str, &str, &str)> = rows.iter().map(|row| (row["ip"].as_str(), row["port"].as_str(), row["state"].as_str())).collect();
+        assert_eq!(ports, vec![
+            ("192.168.0.10", "22", "open"),
+            ("192.168.0.10", "80", "open"),
+            ("192.168.0.10", "443", "closed"),
+            ("192.168.0.11", "", ""),
+        ]);
+        assert!(rows[..3].iter().all(|row| row["protocol"] == "tcp"));
+    }
+}
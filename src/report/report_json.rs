@@ -0,0 +1,48 @@
+use std::io::{self, Write};
+
+use crate::network::network_core::PortScanResult;
+use crate::report::ScanReport;
+
+pub fn write_json_report(out: &mut dyn Write, report: &ScanReport) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, report)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+pub fn write_ndjson_result(out: &mut dyn Write, result: &PortScanResult) -> io::Result<()> {
+    // One host per line, flushed right away so consumers can process hosts while the scan is running
+    serde_json::to_writer(&mut *out, result)?;
+    writeln!(out)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::sample_report;
+
+    #[test]
+    fn json_report_round_trips() {
+        let report = sample_report();
+        let mut out: Vec<u8> = Vec::new();
+        write_json_report(&mut out, &report).unwrap();
+
+        let parsed: ScanReport = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed, report);
+    }
+
+    #[test]
+    fn ndjson_writes_one_host_per_line() {
+        let report = sample_report();
+        let mut out: Vec<u8> = Vec::new();
+        for result in report.hosts.iter() {
+            write_ndjson_result(&mut out, result).unwrap();
+        }
+
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(lines.len(), report.hosts.len());
+        for (line, result) in lines.iter().zip(report.hosts.iter()) {
+            assert_eq!(&serde_json::from_str::<PortScanResult>(line).unwrap(), result);
+        }
+    }
+}
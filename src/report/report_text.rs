@@ -0,0 +1,273 @@
+use std::io::{self, Write};
+
+use chrono::{DateTime, Utc};
+
+use crate::network::network_core::{PingStats, PortInfo, PortScanResult, PortState, Reason, Status};
+use crate::network::network_http::HttpInfo;
+use crate::network::network_interfaces::InterfaceInfo;
+use crate::network::network_tls::TlsInfo;
+use crate::report::ScanReport;
+
+pub fn print_results(out: &mut dyn Write, report: &ScanReport) -> io::Result<()> {
+    
+    writeln!(out, "--------------------------------------------------------------------------------------------------------------------------------\n")?;
+    writeln!(out, "RESULTS:")?;
+    writeln!(out, "Total IPs scanned: {}", report.hosts.len())?;
+    writeln!(out, "IPs UP: {}", report.n_up())?;
+    
+    writeln!(out, "--------------------------------------------------------------------------------------------------------------------------------\n")?;
+    writeln!(out, "IPs UP:")?;
+    for result in report.hosts.iter() {
+        if result.status == Status::Up {
+            write!(
+                out,
+                "IP: {:?} ; Status: {:?} ; Reason: {} ; Discovery: {:?} ; Hostname: {} ; Open TCP Ports: {:?}",
+                result.ip_address,
+                result.status,
+                format_reason(&result.reason),
+                result.discovery,
+                format_hostname(result),
+                result.open_tcp_ports,
+            )?;
+            if let Some(not_shown) = format_not_shown(&result.tcp_ports) {
+                write!(out, " {}", not_shown)?;
+            }
+            if !result.udp_ports.is_empty() {
+                write!(out, " ; UDP Ports: {}", format_udp_ports(&result.udp_ports))?;
+            }
+            let services: Vec<String> = result.tcp_ports.iter().filter_map(format_service).collect();
+            if !services.is_empty() {
+                write!(out, " ; Services: {}", services.join(", "))?;
+            }
+            if let Some(mac_address) = result.mac_address.as_deref() {
+                write!(out, " ; MAC: {} ({})", mac_address, result.vendor.as_deref().unwrap_or("Unknown vendor"))?;
+            }
+            if let Some(ping) = result.ping.as_ref() {
+                write!(out, " ; {}", format_ping_stats(ping))?;
+            }
+            writeln!(out)?;
+            for text in result.dns_txt.iter() {
+                writeln!(out, "-- DNS TXT: {:?}", text)?;
+            }
+            for info in result.tcp_ports.iter() {
+                if let Some(tls) = info.tls.as_ref() {
+                    writeln!(out, "-- TLS port {}: {}", info.port, format_tls(tls, report.metadata.end_time))?;
+                }
+                if let Some(http) = info.http.as_ref() {
+                    writeln!(out, "-- HTTP port {}: {}", info.port, format_http(http))?;
+                }
+            }
+        }
+    };
+
+    writeln!(out, "\nIPs DOWN:")?;
+    for result in report.hosts.iter() {
+        if result.status == Status::Down {
+            write!(out, "IP: {:?} ; Status: {:?} ; Reason: {} ; Discovery: {:?}", result.ip_address, result.status, format_reason(&result.reason), result.discovery)?;
+            // Down hosts are only looked up on request
+            if result.hostname.is_some() || result.dns_error.is_some() {
+                write!(out, " ; Hostname: {}", format_hostname(result))?;
+            }
+            writeln!(out)?;
+        }
+    };
+
+    out.flush()
+}
+
+// Same layout as the summary of `ping`, e.g. "RTT min/avg/max/stddev: 0.041/0.050/0.061/0.008 ms ; Packet loss: 0% (3/3) ; TTL: 64"
+fn format_ping_stats(ping: &PingStats) -> String {
+    let mut text = match (ping.rtt_min_ms, ping.rtt_avg_ms, ping.rtt_max_ms, ping.rtt_stddev_ms) {
+        (Some(min), Some(avg), Some(max), Some(stddev)) => format!("RTT min/avg/max/stddev: {:.3}/{:.3}/{:.3}/{:.3} ms ; ", min, avg, max, stddev),
+        _ => String::new(),
+    };
+    text.push_str(&format!("Packet loss: {}% ({}/{})", ping.packet_loss, ping.received, ping.transmitted));
+    if let Some(ttl) = ping.ttl {
+        text.push_str(&format!(" ; TTL: {}", ttl));
+    }
+    text
+}
+
+// The ports that may be open with their state, the others only counted, e.g. "[53 open (domain), 123 open|filtered] (7 closed)"
+fn format_udp_ports(ports: &[PortInfo]) -> String {
+    let listed: Vec<String> = ports.iter()
+        .filter(|info| matches!(info.state, PortState::Open | PortState::OpenFiltered))
+        .map(|info| match info.service.as_ref() {
+            Some(service) => format!("{} {} ({})", info.port, info.state.name(), service.name),
+            None => format!("{} {}", info.port, info.state.name()),
+        })
+        .collect();
+    let mut text = format!("[{}]", listed.join(", "));
+    if let Some(not_shown) = format_not_shown(ports) {
+        text.push(' ');
+        text.push_str(&not_shown);
+    }
+    text
+}
+
+// The ports that are not listed, counted by state like nmap's "Not shown" line,
+// e.g. "(995 closed, 3 filtered, 2 error: host-unreach)". None if every port is listed
+fn format_not_shown(ports: &[PortInfo]) -> Option<String> {
+    let n_closed = ports.iter().filter(|info| info.state == PortState::Closed).count();
+    let n_filtered = ports.iter().filter(|info| info.state == PortState::Filtered).count();
+    let mut counts: Vec<String> = [(n_closed, "closed"), (n_filtered, "filtered")].into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, state)| format!("{} {}", count, state))
+        .collect();
+
+    let mut errors: Vec<&str> = ports.iter()
+        .filter_map(|info| match info.state {
+            PortState::Error(error) => Some(error.name()),
+            _ => None,
+        })
+        .collect();
+    errors.sort_unstable();
+    for error in errors.chunk_by(|a, b| a == b) {
+        counts.push(format!("{} error: {}", error.len(), error[0]));
+    }
+    (!counts.is_empty()).then(|| format!("({})", counts.join(", ")))
+}
+
+// e.g. "22/ssh OpenSSH 8.9p1", None if no service was identified on the port
+fn format_service(info: &PortInfo) -> Option<String> {
+    let service = info.service.as_ref()?;
+    let mut text = format!("{}/{}", info.port, service.name);
+    for detail in [service.product.as_deref(), service.version.as_deref()].into_iter().flatten() {
+        text.push(' ');
+        text.push_str(detail);
+    }
+    Some(text)
+}
+
+// e.g. "Status: 301 ; Server: nginx ; Location: https://example.com/ ; Favicon hash: -1234"
+fn format_http(http: &HttpInfo) -> String {
+    let mut text = format!("Status: {}", http.status_code);
+    if let Some(server) = http.server.as_deref() {
+        text.push_str(&format!(" ; Server: {:?}", server));
+    }
+    if let Some(title) = http.title.as_deref() {
+        text.push_str(&format!(" ; Title: {:?}", title));
+    }
+    if let Some(location) = http.location.as_deref() {
+        text.push_str(&format!(" ; Location: {}", location));
+    }
+    if let Some(hash) = http.favicon_hash {
+        text.push_str(&format!(" ; Favicon hash: {}", hash));
+    }
+    text
+}
+
+// Protocol, cipher and certificate, with a warning for certificates that can't be trusted at the time of the scan
+fn format_tls(tls: &TlsInfo, at: DateTime<Utc>) -> String {
+    let mut text = format!("{} {}", tls.protocol, tls.cipher);
+    let Some(certificate) = tls.certificate.as_ref() else {
+        text.push_str(" ; Certificate: unknown");
+        return text;
+    };
+    text.push_str(&format!(" ; Subject: {} ; Issuer: {}", certificate.subject, certificate.issuer));
+    if !certificate.subject_alt_names.is_empty() {
+        text.push_str(&format!(" ; SANs: {}", certificate.subject_alt_names.join(", ")));
+    }
+    text.push_str(&format!(
+        " ; Valid: {} to {} ; Key: {} {}",
+        certificate.not_before.format("%Y-%m-%d"),
+        certificate.not_after.format("%Y-%m-%d"),
+        certificate.key_type,
+        certificate.key_bits.map(|bits| bits.to_string()).unwrap_or(String::from("unknown size")),
+    ));
+    if certificate.is_expired(at) {
+        text.push_str(" ; EXPIRED");
+    } else if certificate.is_not_yet_valid(at) {
+        text.push_str(" ; NOT YET VALID");
+    }
+    if certificate.self_signed {
+        text.push_str(" ; SELF-SIGNED");
+    }
+    text
+}
+
+// The name in quotes, or why there is none, e.g. "Unknown (timeout)"
+fn format_hostname(result: &PortScanResult) -> String {
+    match (result.hostname.as_deref(), result.dns_error.as_ref()) {
+        (Some(hostname), _) => format!("{:?}", hostname),
+        (None, Some(error)) => format!("Unknown ({})", error),
+        (None, None) => String::from("Unknown"),
+    }
+}
+
+// e.g. "echo-reply" or "conn-refused (port 443)"
+fn format_reason(reason: &Reason) -> String {
+    match reason.port() {
+        Some(port) => format!("{} (port {})", reason.name(), port),
+        None => reason.name().to_string(),
+    }
+}
+
+pub fn print_interfaces(out: &mut dyn Write, interfaces: &[InterfaceInfo]) -> io::Result<()> {
+    // Skip loopback interfaces and interfaces without addresses.
+    // Interfaces that are down are still shown, Windows reports every interface as down
+    for interface in interfaces.iter().filter(|interface| !interface.is_loopback && !interface.addresses.is_empty()) {
+
+        // Show the interface description
+        if !interface.description.is_empty() {
+            writeln!(out, "Interface: {} - {}", interface.name, interface.description)?;
+        } else {
+            writeln!(out, "Interface: {}", interface.name)?;
+        }
+        writeln!(
+            out,
+            "-- MAC: {} ; MTU: {} ; Flags: {}",
+            interface.mac_address.as_deref().unwrap_or("none"),
+            interface.mtu.map(|mtu| mtu.to_string()).unwrap_or(String::from("unknown")),
+            interface.flags.join(","),
+        )?;
+
+        // Print the IPs of possibly relevant interfaces
+        for address in interface.addresses.iter().filter(|address| address.prefix > 0) {
+            if address.ip.is_ipv4() {
+                writeln!(out, "-- Possible interesting IPv4 Address: {}/{}", address.ip, address.prefix)?;
+            } else if !address.is_link_local() {
+                writeln!(out, "-- Possible interesting IPv6 Address: {}/{} (scan a /120 of it)", address.ip, address.prefix)?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::network_core::ProbeError;
+    use crate::report::sample_report;
+
+    fn port(port: u16, state: PortState) -> PortInfo {
+        PortInfo { port, state, service: None, tls: None, http: None }
+    }
+
+    #[test]
+    fn format_not_shown_counts_by_state() {
+        let mut ports: Vec<PortInfo> = (1..=995).map(|number| port(number, PortState::Closed)).collect();
+        ports.extend([port(2000, PortState::Filtered), port(2001, PortState::Filtered), port(2002, PortState::Filtered)]);
+        ports.push(port(2003, PortState::Error(ProbeError::NetworkUnreachable)));
+        ports.push(port(2004, PortState::Error(ProbeError::HostUnreachable)));
+        ports.push(port(2005, PortState::Error(ProbeError::HostUnreachable)));
+        ports.push(port(22, PortState::Open));
+        assert_eq!(format_not_shown(&ports).unwrap(), "(995 closed, 3 filtered, 2 error: host-unreach, 1 error: net-unreach)");
+    }
+
+    #[test]
+    fn format_not_shown_is_empty_if_every_port_is_listed() {
+        assert_eq!(format_not_shown(&[port(22, PortState::Open)]), None);
+        assert_eq!(format_not_shown(&[]), None);
+        assert_eq!(format_not_shown(&[port(22, PortState::Filtered)]).unwrap(), "(1 filtered)");
+    }
+
+    #[test]
+    fn print_results_appends_the_ports_not_shown() {
+        let mut out: Vec<u8> = Vec::new();
+        print_results(&mut out, &sample_report()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("Open TCP Ports: [22, 80] (1 closed)"));
+        assert!(text.contains("IP: 192.168.0.11 ; Status: Down ; Reason: no-response"));
+    }
+}
@@ -0,0 +1,341 @@
+use std::io::{self, Write};
+
+use chrono::{DateTime, Local, Utc};
+
+use crate::network::network_core::{PortInfo, PortState, Status};
+use crate::network::network_helpers::{parse_port_spec, well_known_service};
+use crate::network::network_tls::CertificateInfo;
+use crate::report::ScanReport;
+
+// Version of the nmap XML format we mimic, see https://nmap.org/book/nmap-dtd.html
+const XML_OUTPUT_VERSION: &str = "1.05";
+// Like nmap, a state with more ports than this is summarised in <extraports> instead of listing every port
+const MAX_LISTED_PORTS: usize = 25;
+
+// Values come from remote hosts, so characters XML doesn't allow at all (most C0 controls, U+FFFE and U+FFFF) are dropped.
+// Tab, newline and carriage return are written as references, a parser would turn them into spaces in an attribute
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' => escaped.push_str("&#x9;"),
+            '\n' => escaped.push_str("&#xa;"),
+            '\r' => escaped.push_str("&#xd;"),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {},
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn nmap_timestr(time: &DateTime<Utc>) -> String {
+    // nmap writes ctime style local timestamps, e.g. "Mon Nov 18 10:00:00 2024"
+    time.with_timezone(&Local).format("%a %b %e %H:%M:%S %Y").to_string()
+}
+
+// Same lines as the output of nmap's ssl-cert script
+fn ssl_cert_output(certificate: &CertificateInfo) -> String {
+    let mut lines = vec![format!("Subject: {}", certificate.subject)];
+    if !certificate.subject_alt_names.is_empty() {
+        lines.push(format!("Subject Alternative Name: {}", certificate.subject_alt_names.join(", ")));
+    }
+    lines.push(format!("Issuer: {}", certificate.issuer));
+    lines.push(format!("Public Key type: {}", certificate.key_type.to_lowercase()));
+    if let Some(bits) = certificate.key_bits {
+        lines.push(format!("Public Key bits: {}", bits));
+    }
+    lines.push(format!("Not valid before: {}", certificate.not_before.format("%Y-%m-%dT%H:%M:%S")));
+    lines.push(format!("Not valid after:  {}", certificate.not_after.format("%Y-%m-%dT%H:%M:%S")));
+    lines.join("\n")
+}
+
+fn write_port(out: &mut dyn Write, protocol: &str, info: &PortInfo, syn_scan: bool) -> io::Result<()> {
+    write!(out, "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"0\"/>", protocol, info.port, port_state(info.state), port_reason(protocol, info.state, syn_scan))?;
+    // A probed service is certain, a name from the port table only a guess, like in nmap
+    if let Some(service) = info.service.as_ref() {
+        write!(out, "<service name=\"{}\"", escape_xml(&service.name))?;
+        if let Some(product) = service.product.as_deref() {
+            write!(out, " product=\"{}\"", escape_xml(product))?;
+        }
+        if let Some(version) = service.version.as_deref() {
+            write!(out, " version=\"{}\"", escape_xml(version))?;
+        }
+        write!(out, " method=\"probed\" conf=\"10\"/>")?;
+    } else if let Some(service) = well_known_service(info.port).filter(|_| protocol == "tcp") {
+        write!(out, "<service name=\"{}\" method=\"table\" conf=\"3\"/>", service)?;
+    }
+    if let Some(certificate) = info.tls.as_ref().and_then(|tls| tls.certificate.as_ref()) {
+        write!(out, "<script id=\"ssl-cert\" output=\"{}\"/>", escape_xml(&ssl_cert_output(certificate)))?;
+    }
+    // Like the http-title and http-server-header scripts of nmap
+    if let Some(http) = info.http.as_ref() {
+        if let Some(title) = http.title.as_deref() {
+            write!(out, "<script id=\"http-title\" output=\"{}\"/>", escape_xml(title))?;
+        }
+        if let Some(server) = http.server.as_deref() {
+            write!(out, "<script id=\"http-server-header\" output=\"{}\"/>", escape_xml(server))?;
+        }
+    }
+    writeln!(out, "</port>")
+}
+
+// The ports of one protocol, the closed and filtered ones collapsed if there are many of them
+// nmap's DTD wants every <extraports> of a host before its first <port>, so the collapsed states of all protocols are written first
+fn write_ports(out: &mut dyn Write, protocols: &[(&str, &[PortInfo])], syn_scan: bool) -> io::Result<()> {
+    let collapsed: Vec<Vec<&str>> = protocols.iter()
+        .map(|(_, ports)| {
+            ["closed", "filtered", "open|filtered"].into_iter()
+                .filter(|state| ports.iter().filter(|info| port_state(info.state) == *state).count() > MAX_LISTED_PORTS)
+                .collect()
+        })
+        .collect();
+    for ((protocol, ports), collapsed) in protocols.iter().zip(collapsed.iter()) {
+        for state in collapsed.iter() {
+            let mut reasons: Vec<&str> = ports.iter()
+                .filter(|info| port_state(info.state) == *state)
+                .map(|info| port_reason(protocol, info.state, syn_scan))
+                .collect();
+            writeln!(out, "<extraports state=\"{}\" count=\"{}\">", state, reasons.len())?;
+            reasons.sort_unstable();
+            for reason in reasons.chunk_by(|a, b| a == b) {
+                writeln!(out, "<extrareasons reason=\"{}\" count=\"{}\" proto=\"{}\"/>", reason[0], reason.len(), protocol)?;
+            }
+            writeln!(out, "</extraports>")?;
+        }
+    }
+    for ((protocol, ports), collapsed) in protocols.iter().zip(collapsed.iter()) {
+        for info in ports.iter().filter(|info| !collapsed.contains(&port_state(info.state))) {
+            write_port(out, protocol, info, syn_scan)?;
+        }
+    }
+    Ok(())
+}
+
+// nmap has no error state, it reports ports behind an unreachable host as filtered
+fn port_state(state: PortState) -> &'static str {
+    match state {
+        PortState::Error(_) => "filtered",
+        _ => state.name(),
+    }
+}
+
+// nmap's reason for the state of a port
+fn port_reason(protocol: &str, state: PortState, syn_scan: bool) -> &'static str {
+    match (protocol, state) {
+        (_, PortState::Error(error)) => error.name(),
+        ("tcp", PortState::Open) => "syn-ack",
+        ("tcp", PortState::Closed) if syn_scan => "reset",
+        ("tcp", PortState::Closed) => "conn-refused",
+        (_, PortState::Open) => "udp-response",
+        (_, PortState::Closed) => "port-unreach",
+        (_, PortState::Filtered | PortState::OpenFiltered) => "no-response",
+    }
+}
+
+pub fn write_xml_report(out: &mut dyn Write, report: &ScanReport) -> io::Result<()> {
+    let metadata = &report.metadata;
+    let n_up = report.n_up();
+    let n_total = report.hosts.len() as u32;
+    let elapsed = (metadata.end_time - metadata.start_time).num_milliseconds() as f64 / 1000.0;
+    let n_services = parse_port_spec(&metadata.ports).map(|ports| ports.len()).unwrap_or(0);
+
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(out, "<!DOCTYPE nmaprun>")?;
+    writeln!(
+        out,
+        "<nmaprun scanner=\"network_scanner\" args=\"{}\" start=\"{}\" startstr=\"{}\" version=\"{}\" xmloutputversion=\"{}\">",
+        escape_xml(&metadata.command),
+        metadata.start_time.timestamp(),
+        nmap_timestr(&metadata.start_time),
+        env!("CARGO_PKG_VERSION"),
+        XML_OUTPUT_VERSION,
+    )?;
+    writeln!(
+        out,
+        "<scaninfo type=\"{}\" protocol=\"tcp\" numservices=\"{}\" services=\"{}\"/>",
+        if metadata.syn_scan { "syn" } else { "connect" },
+        n_services,
+        escape_xml(&metadata.ports),
+    )?;
+    if let Some(udp_ports) = metadata.udp_ports.as_deref() {
+        writeln!(
+            out,
+            "<scaninfo type=\"udp\" protocol=\"udp\" numservices=\"{}\" services=\"{}\"/>",
+            parse_port_spec(udp_ports).map(|ports| ports.len()).unwrap_or(0),
+            escape_xml(udp_ports),
+        )?;
+    }
+
+    for result in report.hosts.iter() {
+        let addrtype = if result.ip_address.is_ipv4() { "ipv4" } else { "ipv6" };
+
+        writeln!(out, "<host>")?;
+        match result.status {
+            Status::Up => writeln!(out, "<status state=\"up\" reason=\"{}\" reason_ttl=\"{}\"/>", result.reason.name(), result.ping.as_ref().and_then(|ping| ping.ttl).unwrap_or(0))?,
+            Status::Down => writeln!(out, "<status state=\"down\" reason=\"{}\" reason_ttl=\"0\"/>", result.reason.name())?,
+        }
+        writeln!(out, "<address addr=\"{}\" addrtype=\"{}\"/>", result.ip_address, addrtype)?;
+        if let Some(mac_address) = result.mac_address.as_deref() {
+            // nmap writes hardware addresses in upper case
+            match result.vendor.as_deref() {
+                Some(vendor) => writeln!(out, "<address addr=\"{}\" addrtype=\"mac\" vendor=\"{}\"/>", mac_address.to_uppercase(), escape_xml(vendor))?,
+                None => writeln!(out, "<address addr=\"{}\" addrtype=\"mac\"/>", mac_address.to_uppercase())?,
+            }
+        }
+
+        writeln!(out, "<hostnames>")?;
+        if let Some(hostname) = result.hostname.as_deref() {
+            writeln!(out, "<hostname name=\"{}\" type=\"PTR\"/>", escape_xml(hostname))?;
+        }
+        writeln!(out, "</hostnames>")?;
+
+        // Like nmap, ports are only reported for hosts that are up
+        if result.status == Status::Up {
+            writeln!(out, "<ports>")?;
+            write_ports(out, &[("tcp", &result.tcp_ports), ("udp", &result.udp_ports)], metadata.syn_scan)?;
+            writeln!(out, "</ports>")?;
+
+            // nmap reports round trip times in microseconds
+            if let Some(rtt_ms) = result.rtt_ms {
+                let rttvar_ms = result.ping.as_ref().and_then(|ping| ping.rtt_stddev_ms).unwrap_or(0.0);
+                writeln!(out, "<times srtt=\"{}\" rttvar=\"{}\" to=\"{}\"/>", (rtt_ms * 1000.0) as u64, (rttvar_ms * 1000.0) as u64, metadata.timeout_ms as u64 * 1000)?;
+            }
+        }
+        writeln!(out, "</host>")?;
+    }
+
+    writeln!(out, "<runstats>")?;
+    writeln!(
+        out,
+        "<finished time=\"{}\" timestr=\"{}\" elapsed=\"{:.2}\" summary=\"Scan done; {} IP addresses ({} hosts up) scanned in {:.2} seconds\" exit=\"success\"/>",
+        metadata.end_time.timestamp(),
+        nmap_timestr(&metadata.end_time),
+        elapsed,
+        n_total,
+        n_up,
+        elapsed,
+    )?;
+    writeln!(out, "<hosts up=\"{}\" down=\"{}\" total=\"{}\"/>", n_up, n_total - n_up, n_total)?;
+    writeln!(out, "</runstats>")?;
+    writeln!(out, "</nmaprun>")?;
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::network_core::ProbeError;
+    use crate::report::sample_report;
+
+    fn write(report: &ScanReport) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        write_xml_report(&mut out, report).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn escape_xml_escapes_markup_and_newlines() {
+        assert_eq!(escape_xml("<a href=\"x\">Tom & Jerry's</a>\n"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;&#xa;");
+    }
+
+    #[test]
+    fn escape_xml_drops_characters_xml_forbids() {
+        assert_eq!(escape_xml("\x1b[31mx\x00"), "[31mx");
+        assert_eq!(escape_xml("a\x01\x08\x0b\x0c\x1fb\u{fffe}\u{ffff}"), "ab");
+        assert_eq!(escape_xml("tab\there\r\n"), "tab&#x9;here&#xd;&#xa;");
+        assert_eq!(escape_xml("Grüße 🙂 \u{7f}"), "Grüße 🙂 \u{7f}");
+    }
+
+    #[test]
+    fn xml_report_has_the_nmap_structure() {
+        let xml = write(&sample_report());
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE nmaprun>\n<nmaprun scanner=\"network_scanner\""));
+        assert!(xml.contains("<scaninfo type=\"connect\" protocol=\"tcp\" numservices=\"3\" services=\"22,80,443\"/>"));
+        assert!(xml.contains("<status state=\"up\" reason=\"syn-ack\""));
+        assert!(xml.contains("<address addr=\"192.168.0.10\" addrtype=\"ipv4\"/>"));
+        assert!(xml.contains("<hostname name=\"nas.lan\" type=\"PTR\"/>"));
+        assert!(xml.contains("<hosts up=\"1\" down=\"1\" total=\"2\"/>"));
+        assert!(xml.trim_end().ends_with("</nmaprun>"));
+    }
+
+    #[test]
+    fn xml_report_lists_ports_of_hosts_that_are_up_only() {
+        let xml = write(&sample_report());
+        assert!(xml.contains("<port protocol=\"tcp\" portid=\"22\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"0\"/><service name=\"ssh\" method=\"table\" conf=\"3\"/></port>"));
+        assert!(xml.contains("<port protocol=\"tcp\" portid=\"443\"><state state=\"closed\" reason=\"conn-refused\" reason_ttl=\"0\"/>"));
+        assert_eq!(xml.matches("<ports>").count(), 1);
+    }
+
+    #[test]
+    fn xml_report_writes_the_extraports_of_every_protocol_before_the_ports() {
+        let mut report = sample_report();
+        report.hosts[0].tcp_ports.extend((1000..1030).map(|number| port(number, PortState::Closed)));
+        report.hosts[0].udp_ports = (1..=30).map(|number| port(number, PortState::OpenFiltered)).collect();
+        report.hosts[0].udp_ports.push(port(53, PortState::Open));
+        let xml = write(&report);
+
+        assert!(xml.contains("<extraports state=\"closed\" count=\"31\">\n<extrareasons reason=\"conn-refused\" count=\"31\" proto=\"tcp\"/>\n</extraports>"));
+        assert!(xml.contains("<extraports state=\"open|filtered\" count=\"30\">\n<extrareasons reason=\"no-response\" count=\"30\" proto=\"udp\"/>\n</extraports>"));
+        assert!(xml.rfind("</extraports>").unwrap() < xml.find("<port ").unwrap());
+        assert_eq!(xml.matches("<port ").count(), 3);
+        assert!(xml.contains("<port protocol=\"udp\" portid=\"53\"><state state=\"open\" reason=\"udp-response\""));
+    }
+
+    #[test]
+    fn xml_report_escapes_the_command_line() {
+        let mut report = sample_report();
+        report.metadata.command = String::from("network_scanner \"lab & office\"");
+        assert!(write(&report).contains("args=\"network_scanner &quot;lab &amp; office&quot;\""));
+    }
+
+    fn port(port: u16, state: PortState) -> PortInfo {
+        PortInfo { port, state, service: None, tls: None, http: None }
+    }
+
+    fn write_tcp_ports(ports: &[PortInfo], syn_scan: bool) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        write_ports(&mut out, &[("tcp", ports)], syn_scan).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn port_reason_names_the_answer_like_nmap() {
+        assert_eq!(port_reason("tcp", PortState::Open, false), "syn-ack");
+        assert_eq!(port_reason("tcp", PortState::Closed, true), "reset");
+        assert_eq!(port_reason("tcp", PortState::Closed, false), "conn-refused");
+        assert_eq!(port_reason("tcp", PortState::Filtered, true), "no-response");
+        assert_eq!(port_reason("udp", PortState::Open, false), "udp-response");
+        assert_eq!(port_reason("udp", PortState::Closed, false), "port-unreach");
+        assert_eq!(port_reason("udp", PortState::OpenFiltered, false), "no-response");
+        assert_eq!(port_reason("tcp", PortState::Error(ProbeError::NetworkUnreachable), false), "net-unreach");
+        assert_eq!(port_state(PortState::Error(ProbeError::HostUnreachable)), "filtered");
+    }
+
+    #[test]
+    fn write_ports_lists_a_few_ports() {
+        let xml = write_tcp_ports(&[port(22, PortState::Open), port(23, PortState::Filtered), port(24, PortState::Error(ProbeError::HostUnreachable))], true);
+        assert!(!xml.contains("<extraports"));
+        assert!(xml.contains("portid=\"23\"><state state=\"filtered\" reason=\"no-response\""));
+        assert!(xml.contains("portid=\"24\"><state state=\"filtered\" reason=\"host-unreach\""));
+    }
+
+    #[test]
+    fn write_ports_summarises_many_ports_of_a_state() {
+        let mut ports: Vec<PortInfo> = (1..=30).map(|number| port(number, PortState::Closed)).collect();
+        ports.extend((31..=50).map(|number| port(number, PortState::Filtered)));
+        ports.extend((51..=60).map(|number| port(number, PortState::Error(ProbeError::HostUnreachable))));
+        ports.push(port(80, PortState::Open));
+        let xml = write_tcp_ports(&ports, true);
+
+        assert!(xml.contains("<extraports state=\"closed\" count=\"30\">\n<extrareasons reason=\"reset\" count=\"30\" proto=\"tcp\"/>\n</extraports>"));
+        assert!(xml.contains("<extraports state=\"filtered\" count=\"30\">\n<extrareasons reason=\"host-unreach\" count=\"10\" proto=\"tcp\"/>\n<extrareasons reason=\"no-response\" count=\"20\" proto=\"tcp\"/>\n</extraports>"));
+        assert_eq!(xml.matches("<port ").count(), 1);
+        assert!(xml.contains("portid=\"80\"><state state=\"open\""));
+    }
+}
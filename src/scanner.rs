@@ -0,0 +1,787 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::Stream;
+use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
+use tokio::task;
+
+use crate::error::ScanError;
+use crate::network::network_core::{ping_host_surge, ping_host_tcp, scan_ports_tcp, AdaptiveTimeout, DiscoveryMethod, DnsCache, DnsClient, PingClients, PingOptions, PortScanResult, PortState, Reason, RttEstimate, Status, DEFAULT_DNS_TIMEOUT, TCP_PING_PORTS, TCP_PORTS};
+use crate::network::network_arp::{arp_sweep, neighbour_mac, ArpSweep};
+use crate::network::network_helpers::validate_range;
+use crate::network::network_interfaces::{analyse_interfaces, local_addresses, InterfaceAddress};
+use crate::network::network_http::{fingerprint_http_ports, Scheme, HTTPS_PORTS, HTTP_PORTS};
+use crate::network::network_oui::OuiTable;
+use crate::network::network_services::{detect_services, DEFAULT_SERVICE_TIMEOUT};
+use crate::network::network_syn::{scan_ports_syn, syn_scan_permitted, SynScanner};
+use crate::network::network_targets::TargetSet;
+use crate::network::network_tls::{inspect_tls_ports, TLS_PORTS};
+use crate::network::network_udp::{scan_ports_udp, DEFAULT_UDP_TIMEOUT, UDP_PORTS};
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+const DEFAULT_HOSTS_IN_FLIGHT: usize = 64;
+// Every port probe holds a socket, stay well below the common limit of 1024 open files
+const DEFAULT_PORTS_IN_FLIGHT: usize = 256;
+
+// Which probes are run against every host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Probes {
+    // ARP requests to the targets on directly connected Ethernet networks, needs root or CAP_NET_RAW
+    pub arp: bool,
+    // ICMP echo to decide if a host is up
+    pub icmp: bool,
+    // TCP connects to the discovery ports to decide if a host is up, also used if ICMP is not permitted.
+    // With neither ICMP nor TCP discovery, every host is assumed to be up like `nmap -Pn`
+    pub tcp_discovery: bool,
+    // TCP connect scan of the selected ports
+    pub tcp: bool,
+    // Scan the TCP ports with SYN packets instead of connects, which also tells closed from filtered ports, like `nmap -sS`.
+    // Needs root or CAP_NET_RAW, falls back to the connect scan without
+    pub syn: bool,
+    // UDP scan of the selected UDP ports with a payload for the protocol of every port
+    pub udp: bool,
+    // Reverse DNS lookup of the hostname of the hosts that are up
+    pub reverse_dns: bool,
+    // Also look up the hostname of the hosts that are down, like `nmap -R`
+    pub reverse_dns_all: bool,
+    // Look up the TXT records of the hostname, only with a built-in DNS client
+    pub dns_txt: bool,
+    // Banner grabbing and protocol probes on the open TCP ports to identify the service, like `nmap -sV`
+    pub service_detection: bool,
+    // TLS handshake with the TLS ports and the ports where service detection found TLS, records the certificate
+    pub tls: bool,
+    // Fetch / from the HTTP ports and the ports where service detection found HTTP: status, server, title, redirect and favicon hash
+    pub http: bool,
+}
+
+impl Default for Probes {
+    fn default() -> Self {
+        Probes {
+            arp: true,
+            icmp: true,
+            tcp_discovery: false,
+            tcp: true,
+            syn: false,
+            udp: false,
+            reverse_dns: true,
+            reverse_dns_all: false,
+            dns_txt: false,
+            service_detection: false,
+            tls: false,
+            http: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScannerBuilder {
+    targets: Vec<(IpAddr, IpAddr)>,
+    target_set: TargetSet,
+    ports: Vec<u16>,
+    udp_ports: Vec<u16>,
+    discovery_ports: Vec<u16>,
+    connect_timeout: Duration,
+    dns_timeout: Duration,
+    dns_client: Option<DnsClient>,
+    service_timeout: Duration,
+    udp_timeout: Duration,
+    adaptive_timeout: bool,
+    concurrency: usize,
+    port_concurrency: usize,
+    probes: Probes,
+    ping: PingOptions,
+    oui: OuiTable,
+}
+
+impl Default for ScannerBuilder {
+    fn default() -> Self {
+        ScannerBuilder {
+            targets: Vec::new(),
+            target_set: TargetSet::new(),
+            ports: TCP_PORTS.to_vec(),
+            udp_ports: UDP_PORTS.to_vec(),
+            discovery_ports: TCP_PING_PORTS.to_vec(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            dns_timeout: DEFAULT_DNS_TIMEOUT,
+            dns_client: None,
+            service_timeout: DEFAULT_SERVICE_TIMEOUT,
+            udp_timeout: DEFAULT_UDP_TIMEOUT,
+            adaptive_timeout: false,
+            concurrency: DEFAULT_HOSTS_IN_FLIGHT,
+            port_concurrency: DEFAULT_PORTS_IN_FLIGHT,
+            probes: Probes::default(),
+            ping: PingOptions::default(),
+            oui: OuiTable::embedded(),
+        }
+    }
+}
+
+impl ScannerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a single address
+    pub fn target(mut self, ip: IpAddr) -> Self {
+        self.targets.push((ip, ip));
+        self
+    }
+
+    // Add an inclusive range of addresses of the same family
+    pub fn target_range(mut self, start: IpAddr, end: IpAddr) -> Self {
+        self.targets.push((start, end));
+        self
+    }
+
+    // Add several inclusive ranges
+    pub fn targets(mut self, ranges: impl IntoIterator<Item = (IpAddr, IpAddr)>) -> Self {
+        self.targets.extend(ranges);
+        self
+    }
+
+    // Add an already parsed set of targets, e.g. the output of `resolve_targets`
+    pub fn target_set(mut self, targets: &TargetSet) -> Self {
+        self.target_set.extend(targets.ranges().iter().copied());
+        self
+    }
+
+    // TCP ports to probe on every host, defaults to `TCP_PORTS`
+    pub fn ports(mut self, ports: &[u16]) -> Self {
+        self.ports = ports.to_vec();
+        self
+    }
+
+    // UDP ports to probe on every host if the UDP scan is enabled, defaults to `UDP_PORTS`
+    pub fn udp_ports(mut self, ports: &[u16]) -> Self {
+        self.udp_ports = ports.to_vec();
+        self
+    }
+
+    // Ports of the TCP discovery probes, defaults to `TCP_PING_PORTS`
+    pub fn discovery_ports(mut self, ports: &[u16]) -> Self {
+        self.discovery_ports = ports.to_vec();
+        self
+    }
+
+    // Set the TCP connect, ping and DNS timeouts at once
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self.ping.timeout = timeout;
+        self.dns_timeout = timeout;
+        self
+    }
+
+    // Timeout of a single TCP connect
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    // Timeout of a reverse DNS lookup
+    pub fn dns_timeout(mut self, timeout: Duration) -> Self {
+        self.dns_timeout = timeout;
+        self
+    }
+
+    // Ask these nameservers for the hostnames instead of the OS resolver. The client has its own timeout and retries
+    pub fn dns_client(mut self, client: DnsClient) -> Self {
+        self.dns_client = Some(client);
+        self
+    }
+
+    // Time to wait for a banner, the reply to a service probe, a TLS handshake message or an HTTP response
+    pub fn service_timeout(mut self, timeout: Duration) -> Self {
+        self.service_timeout = timeout;
+        self
+    }
+
+    // Time to wait for a reply or an ICMP error after every UDP datagram
+    pub fn udp_timeout(mut self, timeout: Duration) -> Self {
+        self.udp_timeout = timeout;
+        self
+    }
+
+    // Derive the ping and connect timeouts from the measured round trip times instead of using fixed ones.
+    // The configured timeouts are used until the first round trip time is known
+    pub fn adaptive_timeout(mut self, adaptive: bool) -> Self {
+        self.adaptive_timeout = adaptive;
+        self
+    }
+
+    // Maximum number of hosts probed at the same time
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    // Maximum number of TCP port probes in flight, summed over all hosts
+    pub fn port_concurrency(mut self, port_concurrency: usize) -> Self {
+        self.port_concurrency = port_concurrency.max(1);
+        self
+    }
+
+    pub fn probes(mut self, probes: Probes) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    // Number of ICMP echo requests per host, at least one
+    pub fn ping_count(mut self, count: u16) -> Self {
+        self.ping.count = count.max(1);
+        self
+    }
+
+    // Time between two echo requests to the same host
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.ping.interval = interval;
+        self
+    }
+
+    // Time to wait for the reply to a single echo request
+    pub fn ping_timeout(mut self, timeout: Duration) -> Self {
+        self.ping.timeout = timeout;
+        self
+    }
+
+    // Table to look up the vendor of a MAC address, defaults to the embedded one
+    pub fn oui_table(mut self, oui: OuiTable) -> Self {
+        self.oui = oui;
+        self
+    }
+
+    pub fn build(self) -> Result<Scanner, ScanError> {
+        // Ranges from both sources must be scannable, e.g. a huge IPv6 range would never finish
+        for (start, end) in self.targets.iter().chain(self.target_set.ranges()) {
+            validate_range(*start, *end)?;
+        }
+        // Merge everything into one set, so every address is scanned once
+        let mut targets = self.target_set;
+        targets.extend(self.targets);
+        Ok(Scanner {
+            targets,
+            ports: Arc::new(self.ports),
+            udp_ports: Arc::new(self.udp_ports),
+            discovery_ports: Arc::new(self.discovery_ports),
+            connect_timeout: self.connect_timeout,
+            dns_timeout: self.dns_timeout,
+            dns_client: self.dns_client,
+            service_timeout: self.service_timeout,
+            udp_timeout: self.udp_timeout,
+            adaptive_timeout: self.adaptive_timeout,
+            concurrency: self.concurrency,
+            port_concurrency: self.port_concurrency,
+            probes: self.probes,
+            ping: self.ping,
+            oui: Arc::new(self.oui),
+            dns_cache: Arc::new(DnsCache::new()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    targets: TargetSet,
+    ports: Arc<Vec<u16>>,
+    udp_ports: Arc<Vec<u16>>,
+    discovery_ports: Arc<Vec<u16>>,
+    connect_timeout: Duration,
+    dns_timeout: Duration,
+    dns_client: Option<DnsClient>,
+    service_timeout: Duration,
+    udp_timeout: Duration,
+    adaptive_timeout: bool,
+    concurrency: usize,
+    port_concurrency: usize,
+    probes: Probes,
+    ping: PingOptions,
+    oui: Arc<OuiTable>,
+    // Shared by every scan of this scanner, an address is only looked up once
+    dns_cache: Arc<DnsCache>,
+}
+
+impl Scanner {
+    pub fn builder() -> ScannerBuilder {
+        ScannerBuilder::new()
+    }
+
+    // Merged, deduplicated ranges of all targets
+    pub fn targets(&self) -> &[(IpAddr, IpAddr)] {
+        self.targets.ranges()
+    }
+
+    pub fn ports(&self) -> &[u16] {
+        &self.ports
+    }
+
+    pub fn udp_ports(&self) -> &[u16] {
+        &self.udp_ports
+    }
+
+    pub fn discovery_ports(&self) -> &[u16] {
+        &self.discovery_ports
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    pub fn dns_timeout(&self) -> Duration {
+        self.dns_timeout
+    }
+
+    pub fn dns_client(&self) -> Option<&DnsClient> {
+        self.dns_client.as_ref()
+    }
+
+    pub fn service_timeout(&self) -> Duration {
+        self.service_timeout
+    }
+
+    pub fn udp_timeout(&self) -> Duration {
+        self.udp_timeout
+    }
+
+    pub fn adaptive_timeout(&self) -> bool {
+        self.adaptive_timeout
+    }
+
+    pub fn probes(&self) -> Probes {
+        self.probes
+    }
+
+    pub fn ping_options(&self) -> PingOptions {
+        self.ping
+    }
+
+    // Whether the TCP ports are scanned with SYN packets: the SYN scan is enabled and raw sockets are permitted
+    pub fn syn_scan(&self) -> bool {
+        self.probes.tcp && self.probes.syn && syn_scan_permitted()
+    }
+
+    // Total number of addresses the scan will yield a result for
+    pub fn n_targets(&self) -> u64 {
+        self.targets.len()
+    }
+
+    // Start the scan in the background and return a stream yielding every host as soon as it is done.
+    // Must be called from within a tokio runtime. Hosts are pinged over raw ICMP sockets if permitted,
+    // otherwise over unprivileged datagram sockets or, as a last resort, with the TCP discovery probes
+    pub fn scan(&self) -> Result<impl Stream<Item = PortScanResult> + Unpin, ScanError> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        // Create a ping client for every address family we're going to scan
+        let clients = if self.probes.icmp {
+            PingClients::new(
+                self.targets.ranges().iter().any(|(start, _)| start.is_ipv4()),
+                self.targets.ranges().iter().any(|(start, _)| start.is_ipv6()),
+            )?
+        } else {
+            PingClients::new(false, false)?
+        };
+        // Raw TCP sockets for the SYN scan, without permission every host gets the connect scan
+        let syn = if self.probes.tcp && self.probes.syn {
+            SynScanner::new(
+                self.targets.ranges().iter().any(|(start, _)| start.is_ipv4()),
+                self.targets.ranges().iter().any(|(start, _)| start.is_ipv6()),
+            )
+        } else {
+            None
+        };
+
+        let context = Arc::new(ScanContext {
+            clients,
+            syn,
+            ports: Arc::clone(&self.ports),
+            udp_ports: Arc::clone(&self.udp_ports),
+            discovery_ports: Arc::clone(&self.discovery_ports),
+            connect_timeout: self.connect_timeout,
+            dns_timeout: self.dns_timeout,
+            dns_client: self.dns_client.clone(),
+            service_timeout: self.service_timeout,
+            udp_timeout: self.udp_timeout,
+            adaptive: self.adaptive_timeout.then(AdaptiveTimeout::new),
+            probes: self.probes,
+            ping: self.ping,
+            oui: Arc::clone(&self.oui),
+            dns_cache: Arc::clone(&self.dns_cache),
+            local_addresses: local_addresses(&analyse_interfaces()),
+            port_permits: Semaphore::new(self.port_concurrency),
+        });
+        let host_permits = Arc::new(Semaphore::new(self.concurrency));
+        let targets = self.targets.clone();
+
+        let arp = self.probes.arp;
+        let arp_timeout = self.ping.timeout;
+
+        // Work queue: hand out one address after the other, each in its own task as soon as a host slot is free.
+        // A slow host only occupies its own slot instead of blocking the hosts queued behind it
+        task::spawn(async move {
+            // Sweep the local networks first, like nmap. The datalink channel blocks, so it gets its own thread
+            let sweep = if arp {
+                let targets = targets.clone();
+                task::spawn_blocking(move || arp_sweep(&targets, arp_timeout)).await.unwrap_or_default()
+            } else {
+                ArpSweep::default()
+            };
+            let sweep = Arc::new(sweep);
+
+            for ip in targets.iter() {
+                let Ok(permit) = Arc::clone(&host_permits).acquire_owned().await else {
+                    return;
+                };
+                // Stop handing out work if the caller dropped the stream
+                if sender.is_closed() {
+                    return;
+                }
+                let context = Arc::clone(&context);
+                let sweep = Arc::clone(&sweep);
+                let sender = sender.clone();
+                task::spawn(async move {
+                    let result = context.scan_host(ip, &sweep).await;
+                    let _ = sender.unbounded_send(result);
+                    drop(permit);
+                });
+            }
+        });
+
+        // The stream ends once the dispatcher and the last host task dropped their senders
+        Ok(receiver)
+    }
+}
+
+// State shared by all host tasks of one scan
+struct ScanContext {
+    clients: PingClients,
+    syn: Option<SynScanner>,
+    ports: Arc<Vec<u16>>,
+    udp_ports: Arc<Vec<u16>>,
+    discovery_ports: Arc<Vec<u16>>,
+    connect_timeout: Duration,
+    dns_timeout: Duration,
+    dns_client: Option<DnsClient>,
+    service_timeout: Duration,
+    udp_timeout: Duration,
+    // Round trip times measured so far, only in adaptive mode
+    adaptive: Option<AdaptiveTimeout>,
+    probes: Probes,
+    ping: PingOptions,
+    oui: Arc<OuiTable>,
+    dns_cache: Arc<DnsCache>,
+    // Addresses of our interfaces, only hosts in their networks have a MAC address we can see
+    local_addresses: Vec<InterfaceAddress>,
+    port_permits: Semaphore,
+}
+
+impl ScanContext {
+    async fn scan_host(&self, ip: IpAddr, sweep: &ArpSweep) -> PortScanResult {
+        // Ping and resolve hostname and tcp port scan
+        // ICMP and TCP discovery run side by side, a host is up as soon as one of them gets an answer
+        let icmp = if self.probes.icmp { self.clients.method_for(ip) } else { None };
+        let tcp_discovery = self.probes.tcp_discovery || (self.probes.icmp && icmp.is_none());
+        // Without any ping the host is assumed to be up, the ARP sweep only adds its hardware address then
+        let assume_up = !self.probes.icmp && !tcp_discovery;
+        let arp = sweep.swept(ip).then_some(DiscoveryMethod::Arp);
+        let discovery: Vec<DiscoveryMethod> = arp.into_iter()
+            .chain(icmp)
+            .chain(tcp_discovery.then_some(DiscoveryMethod::TcpConnect))
+            .collect();
+        let arp_reply = sweep.mac_for(ip);
+
+        let mut ping_options = self.ping;
+        if let Some(estimate) = self.adaptive.as_ref().and_then(AdaptiveTimeout::estimate) {
+            ping_options.timeout = estimate.timeout();
+        }
+        let icmp_probe = async {
+            match icmp {
+                Some(_) => Some(ping_host_surge(&self.clients, ip, &ping_options, self.adaptive.as_ref()).await),
+                None => None,
+            }
+        };
+        // Discovery probes are connects like the port scan and wait as long, not as long as for an echo reply
+        let tcp_probe = async {
+            if tcp_discovery {
+                ping_host_tcp(ip, self.connect_timeout_for(None), &self.discovery_ports, &self.port_permits, self.adaptive.as_ref()).await
+            } else {
+                None
+            }
+        };
+        let (icmp_reply, tcp_reply) = tokio::join!(icmp_probe, tcp_probe);
+        let echo_reply = matches!(icmp_reply, Some((Status::Up, _)));
+        let ping = icmp_reply.map(|(_, stats)| stats);
+
+        let (tcp_ports, syn_scan) = if self.probes.tcp {
+            self.scan_ports(ip, self.connect_timeout_for(ping.as_ref().and_then(RttEstimate::from_ping))).await
+        } else {
+            (Vec::new(), false)
+        };
+        let open_tcp_ports: Vec<u16> = tcp_ports.iter().filter(|(_, state)| *state == PortState::Open).map(|(port, _)| *port).collect();
+        let udp_ports = if self.probes.udp {
+            scan_ports_udp(ip, self.udp_timeout, &self.udp_ports, &self.port_permits).await
+        } else {
+            Vec::new()
+        };
+
+        // Any answer counts, in the end even an open port of a host that ignored the discovery probes
+        let reason = if assume_up {
+            Some(Reason::UserSet)
+        } else if arp_reply.is_some() {
+            Some(Reason::ArpResponse)
+        } else if echo_reply {
+            Some(Reason::EchoReply)
+        } else {
+            tcp_reply
+                .or(open_tcp_ports.first().map(|port| Reason::SynAck(*port)))
+                .or(tcp_ports.iter()
+                    .find(|(_, state)| *state == PortState::Closed)
+                    .map(|(port, _)| if syn_scan { Reason::Reset(*port) } else { Reason::ConnRefused(*port) }))
+                .or(udp_ports.iter().find(|info| info.state == PortState::Open).map(|info| Reason::UdpResponse(info.port)))
+                .or(udp_ports.iter().find(|info| info.state == PortState::Closed).map(|info| Reason::PortUnreach(info.port)))
+        };
+        let status = if reason.is_some() { Status::Up } else { Status::Down };
+
+        // Like nmap, only the hosts that are up are looked up unless all of them should be
+        let lookup = if self.probes.reverse_dns && (status == Status::Up || self.probes.reverse_dns_all) {
+            Some(self.dns_cache.reverse_lookup(ip, self.dns_timeout, self.dns_client.as_ref()).await)
+        } else {
+            None
+        };
+        let (hostname, dns_error) = match lookup {
+            Some(Ok(hostname)) => (Some(hostname), None),
+            Some(Err(error)) => (None, Some(error)),
+            None => (None, None),
+        };
+        let dns_txt = match (self.dns_client.as_ref(), hostname.as_deref()) {
+            (Some(client), Some(hostname)) if self.probes.dns_txt => client.lookup_txt(hostname).await.unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        let services = if self.probes.service_detection {
+            detect_services(ip, &open_tcp_ports, self.service_timeout, &self.port_permits).await
+        } else {
+            Vec::new()
+        };
+        let tls = if self.probes.tls {
+            let tls_ports: Vec<u16> = open_tcp_ports.iter()
+                .copied()
+                .filter(|port| TLS_PORTS.contains(port) || services.iter().any(|(service_port, service)| service_port == port && service.name == "ssl"))
+                .collect();
+            inspect_tls_ports(ip, &tls_ports, hostname.as_deref(), self.service_timeout, &self.port_permits).await
+        } else {
+            Vec::new()
+        };
+        let http = if self.probes.http {
+            // Trust service detection if it ran, otherwise go by the port number
+            let http_ports: Vec<(u16, Scheme)> = open_tcp_ports.iter()
+                .copied()
+                .filter_map(|port| match services.iter().find(|(service_port, _)| *service_port == port) {
+                    Some((_, service)) if service.name == "http" => Some((port, Scheme::Http)),
+                    Some((_, service)) if service.name == "ssl" => Some((port, Scheme::Https)),
+                    Some(_) => None,
+                    None if HTTP_PORTS.contains(&port) => Some((port, Scheme::Http)),
+                    None if HTTPS_PORTS.contains(&port) => Some((port, Scheme::Https)),
+                    None => None,
+                })
+                .collect();
+            fingerprint_http_ports(ip, &http_ports, hostname.as_deref(), self.service_timeout, &self.port_permits).await
+        } else {
+            Vec::new()
+        };
+
+        // The sweep knows the MAC address if it was permitted, otherwise the kernel learned it while we were probing
+        let mac_address = match arp_reply {
+            Some(mac) => Some(mac.to_string()),
+            None if self.local_addresses.iter().any(|address| address.contains(ip)) => neighbour_mac(ip).await,
+            None => None,
+        };
+        let vendor = mac_address.as_deref().and_then(|mac| self.oui.lookup(mac)).map(String::from);
+
+        PortScanResult::new(ip, status, reason.unwrap_or(Reason::NoResponse), discovery, tcp_ports, ping)
+            .with_hostname(hostname, dns_error)
+            .with_dns_txt(dns_txt)
+            .with_mac_address(mac_address, vendor)
+            .with_udp_ports(udp_ports)
+            .with_services(services)
+            .with_tls(tls)
+            .with_http(http)
+    }
+
+    // SYN scan if raw sockets are permitted and the host can be reached with them, otherwise the connect scan.
+    // Also tells which one it was, a closed port means a RST to a SYN or a refused connection
+    async fn scan_ports(&self, ip: IpAddr, timeout: Duration) -> (Vec<(u16, PortState)>, bool) {
+        if let Some(syn) = self.syn.as_ref() {
+            if let Some(states) = scan_ports_syn(syn, ip, timeout, &self.ports, &self.port_permits, self.adaptive.as_ref()).await {
+                return (states, true);
+            }
+        }
+        (scan_ports_tcp(ip, timeout, &self.ports, &self.port_permits, self.adaptive.as_ref()).await, false)
+    }
+
+    // In adaptive mode the own echo replies of a host are the best guess for its connect timeout,
+    // hosts that did not answer get the estimate over all hosts
+    fn connect_timeout_for(&self, host_estimate: Option<RttEstimate>) -> Duration {
+        let Some(adaptive) = self.adaptive.as_ref() else {
+            return self.connect_timeout;
+        };
+        match host_estimate.or_else(|| adaptive.estimate()) {
+            Some(estimate) => estimate.timeout(),
+            None => self.connect_timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use tokio::net::TcpListener;
+
+    // Only the TCP connect scan, every host is assumed to be up
+    const TCP_ONLY: Probes = Probes {
+        arp: false,
+        icmp: false,
+        tcp_discovery: false,
+        tcp: true,
+        syn: false,
+        udp: false,
+        reverse_dns: false,
+        reverse_dns_all: false,
+        dns_txt: false,
+        service_detection: false,
+        tls: false,
+        http: false,
+    };
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn build_merges_all_targets() {
+        let mut set = TargetSet::new();
+        set.insert_range(ip("10.0.0.5"), ip("10.0.0.9"));
+        let scanner = Scanner::builder()
+            .target(ip("10.0.0.1"))
+            .target_range(ip("10.0.0.2"), ip("10.0.0.6"))
+            .target_set(&set)
+            .build()
+            .unwrap();
+        assert_eq!(scanner.targets(), &[(ip("10.0.0.1"), ip("10.0.0.9"))]);
+        assert_eq!(scanner.n_targets(), 9);
+    }
+
+    #[test]
+    fn build_rejects_invalid_ranges() {
+        assert!(matches!(Scanner::builder().target_range(ip("10.0.0.9"), ip("10.0.0.1")).build(), Err(ScanError::InvertedRange { .. })));
+        assert!(matches!(Scanner::builder().target_range(ip("fd00::"), ip("fd00::ffff")).build(), Err(ScanError::InvalidTarget { .. })));
+    }
+
+    #[test]
+    fn build_validates_target_sets() {
+        // The whole IPv6 address space, its size doesn't even fit into a u128
+        let mut set = TargetSet::new();
+        set.insert_range(ip("::"), ip("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"));
+        assert!(matches!(Scanner::builder().target_set(&set).build(), Err(ScanError::InvalidTarget { .. })));
+    }
+
+    #[tokio::test]
+    async fn scan_yields_every_target_with_its_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+
+        let scanner = Scanner::builder()
+            .target_range(ip("127.0.0.1"), ip("127.0.0.2"))
+            .ports(&[open, closed])
+            .connect_timeout(Duration::from_secs(2))
+            .probes(TCP_ONLY)
+            .build()
+            .unwrap();
+        let mut results: Vec<PortScanResult> = scanner.scan().unwrap().collect().await;
+        results.sort_unstable_by_key(|result| result.ip_address);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].ip_address, ip("127.0.0.1"));
+        assert_eq!(results[0].status, Status::Up);
+        assert_eq!(results[0].reason, Reason::UserSet);
+        assert_eq!(results[0].open_tcp_ports, vec![open]);
+        assert_eq!(results[0].tcp_ports.iter().find(|info| info.port == closed).map(|info| info.state), Some(PortState::Closed));
+    }
+
+    #[tokio::test]
+    async fn scan_with_a_single_slot_still_yields_every_host_once() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let scanner = Scanner::builder()
+            .target_range(ip("127.0.0.1"), ip("127.0.0.20"))
+            .ports(&[closed])
+            .connect_timeout(Duration::from_secs(2))
+            .concurrency(1)
+            .port_concurrency(1)
+            .probes(TCP_ONLY)
+            .build()
+            .unwrap();
+        let mut addresses: Vec<IpAddr> = scanner.scan().unwrap().map(|result| result.ip_address).collect().await;
+        addresses.sort_unstable();
+        addresses.dedup();
+        assert_eq!(addresses.len(), 20);
+    }
+
+    #[test]
+    fn timeout_sets_every_timeout_and_specific_ones_take_precedence() {
+        let scanner = Scanner::builder()
+            .target(ip("10.0.0.1"))
+            .timeout(Duration::from_millis(500))
+            .dns_timeout(Duration::from_secs(3))
+            .build()
+            .unwrap();
+        assert_eq!(scanner.connect_timeout(), Duration::from_millis(500));
+        assert_eq!(scanner.ping_options().timeout, Duration::from_millis(500));
+        assert_eq!(scanner.dns_timeout(), Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn tcp_discovery_tells_why_a_host_is_up() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let scanner = Scanner::builder()
+            .target(ip("127.0.0.1"))
+            .discovery_ports(&[closed])
+            .connect_timeout(Duration::from_secs(2))
+            .probes(Probes { tcp_discovery: true, tcp: false, ..TCP_ONLY })
+            .build()
+            .unwrap();
+        let results: Vec<PortScanResult> = scanner.scan().unwrap().collect().await;
+        assert_eq!(results[0].status, Status::Up);
+        assert_eq!(results[0].reason, Reason::ConnRefused(closed));
+        assert_eq!(results[0].discovery, vec![DiscoveryMethod::TcpConnect]);
+    }
+
+    #[tokio::test]
+    async fn tcp_discovery_waits_the_connect_timeout() {
+        // A listener whose accept queue is full drops further SYNs, the connect gets no answer
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut queued = Vec::new();
+        for _ in 0..4 {
+            if let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(100), tokio::net::TcpStream::connect(("127.0.0.1", port))).await {
+                queued.push(stream);
+            }
+        }
+
+        let scanner = Scanner::builder()
+            .target(ip("127.0.0.1"))
+            .discovery_ports(&[port])
+            .connect_timeout(Duration::from_millis(100))
+            .ping_timeout(Duration::from_secs(10))
+            .probes(Probes { tcp_discovery: true, tcp: false, ..TCP_ONLY })
+            .build()
+            .unwrap();
+        let start = std::time::Instant::now();
+        let results: Vec<PortScanResult> = scanner.scan().unwrap().collect().await;
+        assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+        assert_eq!(results[0].status, Status::Down);
+    }
+}